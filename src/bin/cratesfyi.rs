@@ -6,15 +6,26 @@ extern crate log;
 extern crate env_logger;
 extern crate time;
 extern crate rustwide;
+extern crate rustc_serialize;
+extern crate tempdir;
 
 
 use std::env;
-use std::path::PathBuf;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
 
 use clap::{Arg, App, SubCommand};
-use cratesfyi::{DocBuilder, RustwideBuilder, DocBuilderOptions, db};
-use cratesfyi::utils::add_crate_to_queue;
-use cratesfyi::start_web_server;
+use cratesfyi::{DocBuilder, RustwideBuilder, DocBuilderOptions, BuildOverrides, WorldFilter,
+                get_world_run, Metadata, db, storage};
+use cratesfyi::utils::{add_crate_to_queue, get_queued_crates, remove_crate_from_queue,
+                       set_queue_priority, retry_failed_crates, start_rebuild_campaign,
+                       get_rebuild_campaigns, RebuildSelector, REBUILD_PRIORITY};
+use rustc_serialize::json::ToJson;
+use tempdir::TempDir;
+use cratesfyi::{start_preview_server, start_web_server};
 use cratesfyi::db::{add_path_into_database, connect_db};
 
 
@@ -52,8 +63,42 @@ pub fn main() {
                 .short("-k")
                 .long("keep-build-directory")
                 .help("Keeps build directory after build."))
-            .subcommand(SubCommand::with_name("world").about("Builds documentation of every \
-                                                              crate"))
+            .subcommand(SubCommand::with_name("world")
+                .about("Builds documentation of every crate, starting with their latest version")
+                .arg(Arg::with_name("ONLY_LATEST_VERSION")
+                    .long("only-latest-version")
+                    .help("Only builds the latest version of every crate"))
+                .arg(Arg::with_name("SKIP_OLDEST_VERSIONS")
+                    .long("skip-oldest-versions")
+                    .help("Only builds the latest version of every semver compatible series of \
+                           a crate"))
+                .arg(Arg::with_name("DEBUG")
+                    .long("debug")
+                    .help("Stops at the first failed build"))
+                .arg(Arg::with_name("NAME")
+                    .long("name")
+                    .takes_value(true)
+                    .help("Only builds the crates matching this glob, like tokio-*"))
+                .arg(Arg::with_name("CRATES_FILE")
+                    .long("crates-file")
+                    .takes_value(true)
+                    .help("Only builds the crates listed in this file, one per line"))
+                .arg(Arg::with_name("PUBLISHED_AFTER")
+                    .long("published-after")
+                    .takes_value(true)
+                    .help("Only builds the releases published after this YYYY-MM-DD date"))
+                .arg(Arg::with_name("LIMIT")
+                    .long("limit")
+                    .takes_value(true)
+                    .help("Builds at most this number of releases"))
+                .arg(Arg::with_name("RESTART")
+                    .long("restart")
+                    .help("Starts a new run instead of resuming the unfinished one")))
+            .subcommand(SubCommand::with_name("world-status")
+                .about("Shows the progress of the last build world run")
+                .arg(Arg::with_name("JSON")
+                    .long("json")
+                    .help("Prints the progress as JSON")))
             .subcommand(SubCommand::with_name("crate")
                 .about("Builds documentation for a crate")
                 .arg(Arg::with_name("CRATE_NAME")
@@ -64,8 +109,57 @@ pub fn main() {
                     .index(2)
                     .required(true)
                     .help("Version of crate")))
+            .subcommand(SubCommand::with_name("local")
+                .about("Builds documentation for a crate in a local directory, with the same \
+                        settings as docs.rs")
+                .arg(Arg::with_name("PATH")
+                    .index(1)
+                    .required(true)
+                    .help("Path of the crate"))
+                .arg(Arg::with_name("SERVE")
+                    .long("serve")
+                    .help("Serves the documentation from a temporary storage once it's built"))
+                .arg(Arg::with_name("SOCKET_ADDR")
+                    .long("address")
+                    .takes_value(true)
+                    .help("Socket address to serve the documentation on")))
             .subcommand(SubCommand::with_name("add-essential-files")
                 .about("Adds essential files for rustc"))
+            .subcommand(SubCommand::with_name("set-toolchain")
+                .about("Installs a toolchain and pins it as the default toolchain of every \
+                        builder")
+                .arg(Arg::with_name("TOOLCHAIN")
+                    .index(1)
+                    .required(true)
+                    .help("Name of the toolchain, like nightly-2019-10-01")))
+            .subcommand(SubCommand::with_name("add-toolchain")
+                .about("Installs a toolchain crates can request in their metadata")
+                .arg(Arg::with_name("TOOLCHAIN")
+                    .index(1)
+                    .required(true)
+                    .help("Name of the toolchain")))
+            .subcommand(SubCommand::with_name("remove-toolchain")
+                .about("Stops allowing crates to request a toolchain")
+                .arg(Arg::with_name("TOOLCHAIN")
+                    .index(1)
+                    .required(true)
+                    .help("Name of the toolchain")))
+            .subcommand(SubCommand::with_name("list-toolchains")
+                .about("Lists the default toolchain and the toolchains crates can request"))
+            .subcommand(SubCommand::with_name("add-system-dependency")
+                .about("Allows crates to install a system package in their metadata")
+                .arg(Arg::with_name("PACKAGE")
+                    .index(1)
+                    .required(true)
+                    .help("Name of the Debian package")))
+            .subcommand(SubCommand::with_name("remove-system-dependency")
+                .about("Stops allowing crates to install a system package")
+                .arg(Arg::with_name("PACKAGE")
+                    .index(1)
+                    .required(true)
+                    .help("Name of the Debian package")))
+            .subcommand(SubCommand::with_name("list-system-dependencies")
+                .about("Lists the system packages crates can install"))
             .subcommand(SubCommand::with_name("lock").about("Locks cratesfyi daemon to stop \
                                                               building new crates"))
             .subcommand(SubCommand::with_name("unlock")
@@ -79,6 +173,12 @@ pub fn main() {
                 .required(false)
                 .help("Socket address to listen to")))
         .subcommand(SubCommand::with_name("daemon").about("Starts cratesfyi daemon"))
+        .subcommand(SubCommand::with_name("check-metadata")
+            .about("Checks the [package.metadata.docs.rs] table of a manifest")
+            .arg(Arg::with_name("MANIFEST")
+                .index(1)
+                .required(true)
+                .help("Path of the Cargo.toml to check")))
         .subcommand(SubCommand::with_name("database")
             .about("Database operations")
             .subcommand(SubCommand::with_name("move-to-s3"))
@@ -102,7 +202,16 @@ pub fn main() {
             .about("Updates montly release activity \
                                                               chart")
             .subcommand(SubCommand::with_name("update-search-index"))
-            .about("Updates search index"))
+            .about("Updates search index")
+            .subcommand(SubCommand::with_name("delete-crate")
+                .about("Removes a crate or one of its releases, with all of their files")
+                .arg(Arg::with_name("CRATE_NAME")
+                    .index(1)
+                    .required(true)
+                    .help("Name of crate to delete"))
+                .arg(Arg::with_name("CRATE_VERSION")
+                    .index(2)
+                    .help("Version to delete, every release is deleted if it's not set"))))
         .subcommand(SubCommand::with_name("queue")
             .about("Interactions with the build queue")
             .subcommand(SubCommand::with_name("add")
@@ -119,7 +228,159 @@ pub fn main() {
                     .short("p")
                     .long("priority")
                     .help("Priority of build (default: 5) (new crate builds get priority 0)")
-                    .takes_value(true))))
+                    .takes_value(true)))
+            .subcommand(SubCommand::with_name("list")
+                .about("Lists the crates in the build queue, in build order")
+                .arg(Arg::with_name("FAILED")
+                    .long("failed")
+                    .help("Only lists crates which failed to build too many times"))
+                .arg(Arg::with_name("JSON")
+                    .long("json")
+                    .help("Prints the list as JSON")))
+            .subcommand(SubCommand::with_name("remove")
+                .about("Removes a crate from the build queue")
+                .arg(Arg::with_name("CRATE_NAME")
+                    .index(1)
+                    .required(true)
+                    .help("Name of crate to remove"))
+                .arg(Arg::with_name("CRATE_VERSION")
+                    .index(2)
+                    .help("Version of crate to remove, every version is removed if it's not set"))
+                .arg(Arg::with_name("JSON")
+                    .long("json")
+                    .help("Prints the result as JSON")))
+            .subcommand(SubCommand::with_name("set-priority")
+                .about("Changes the priority of a crate in the build queue")
+                .arg(Arg::with_name("CRATE_NAME")
+                    .index(1)
+                    .required(true)
+                    .help("Name of crate to change"))
+                .arg(Arg::with_name("BUILD_PRIORITY")
+                    .index(2)
+                    .required(true)
+                    .help("New priority of the crate (lower is built first)"))
+                .arg(Arg::with_name("CRATE_VERSION")
+                    .long("version")
+                    .takes_value(true)
+                    .help("Version of crate to change, every version is changed if it's not \
+                           set"))
+                .arg(Arg::with_name("JSON")
+                    .long("json")
+                    .help("Prints the result as JSON")))
+            .subcommand(SubCommand::with_name("rebuild")
+                .about("Queues existing releases again as a rebuild campaign")
+                .arg(Arg::with_name("RUSTC_BEFORE")
+                    .long("rustc-before")
+                    .takes_value(true)
+                    .help("Selects releases documented with a rustc older than this version, \
+                           like 1.40.0"))
+                .arg(Arg::with_name("BUILT_BEFORE")
+                    .long("built-before")
+                    .takes_value(true)
+                    .help("Selects releases last built before this date (YYYY-MM-DD)"))
+                .arg(Arg::with_name("FAILED")
+                    .long("failed")
+                    .help("Selects releases which failed to build"))
+                .arg(Arg::with_name("LATEST")
+                    .long("latest")
+                    .help("Selects only the latest release of every crate"))
+                .arg(Arg::with_name("TOP")
+                    .long("top")
+                    .takes_value(true)
+                    .help("Selects releases of the N crates with the most GitHub stars"))
+                .arg(Arg::with_name("CRATES_FILE")
+                    .long("crates-file")
+                    .takes_value(true)
+                    .help("Selects releases of the crates listed in this file, one per line"))
+                .arg(Arg::with_name("DESCRIPTION")
+                    .long("description")
+                    .takes_value(true)
+                    .help("Description of the campaign"))
+                .arg(Arg::with_name("BUILD_PRIORITY")
+                    .short("p")
+                    .long("priority")
+                    .takes_value(true)
+                    .help("Priority of the queued releases (default: 20)"))
+                .arg(Arg::with_name("DRY_RUN")
+                    .long("dry-run")
+                    .help("Only prints the selected releases")))
+            .subcommand(SubCommand::with_name("campaigns")
+                .about("Shows the progress of rebuild campaigns")
+                .arg(Arg::with_name("JSON")
+                    .long("json")
+                    .help("Prints the campaigns as JSON")))
+            .subcommand(SubCommand::with_name("retry-failed")
+                .about("Resets the attempts of crates which failed to build too many times")
+                .arg(Arg::with_name("CRATE_NAME")
+                    .index(1)
+                    .help("Only retries the versions of this crate"))
+                .arg(Arg::with_name("JSON")
+                    .long("json")
+                    .help("Prints the result as JSON"))))
+        .subcommand(SubCommand::with_name("overrides")
+            .about("Manages the build configuration overrides of crates")
+            .subcommand(SubCommand::with_name("list")
+                .about("Lists the build overrides")
+                .arg(Arg::with_name("CRATE_NAME")
+                    .index(1)
+                    .help("Only lists the overrides of this crate"))
+                .arg(Arg::with_name("JSON")
+                    .long("json")
+                    .help("Prints the list as JSON")))
+            .subcommand(SubCommand::with_name("set")
+                .about("Sets build overrides of a crate, keeping the ones which aren't given")
+                .arg(Arg::with_name("CRATE_NAME")
+                    .index(1)
+                    .required(true)
+                    .help("Name of crate"))
+                .arg(Arg::with_name("VERSION_REQ")
+                    .long("version")
+                    .takes_value(true)
+                    .help("Semver requirement of the releases to override (default: *)"))
+                .arg(Arg::with_name("MEMORY")
+                    .long("memory")
+                    .takes_value(true)
+                    .help("Available memory in bytes"))
+                .arg(Arg::with_name("TIMEOUT")
+                    .long("timeout")
+                    .takes_value(true)
+                    .help("Maximum rustdoc execution time in seconds"))
+                .arg(Arg::with_name("NETWORKING")
+                    .long("networking")
+                    .takes_value(true)
+                    .possible_values(&["allowed", "blocked"])
+                    .help("Network access during the build"))
+                .arg(Arg::with_name("MAX_LOG_SIZE")
+                    .long("max-log-size")
+                    .takes_value(true)
+                    .help("Maximum size of a build log in bytes"))
+                .arg(Arg::with_name("TARGETS")
+                    .long("targets")
+                    .takes_value(true)
+                    .use_delimiter(true)
+                    .help("Comma separated targets built in addition to the default ones"))
+                .arg(Arg::with_name("FEATURES")
+                    .long("features")
+                    .takes_value(true)
+                    .use_delimiter(true)
+                    .help("Comma separated features enabled in addition to the crate's ones"))
+                .arg(Arg::with_name("TOOLCHAIN")
+                    .long("toolchain")
+                    .takes_value(true)
+                    .help("Toolchain to build with, like nightly-2019-10-01"))
+                .arg(Arg::with_name("RESET")
+                    .long("reset")
+                    .help("Clears the overrides which aren't given")))
+            .subcommand(SubCommand::with_name("remove")
+                .about("Removes the build overrides of a crate")
+                .arg(Arg::with_name("CRATE_NAME")
+                    .index(1)
+                    .required(true)
+                    .help("Name of crate"))
+                .arg(Arg::with_name("VERSION_REQ")
+                    .long("version")
+                    .takes_value(true)
+                    .help("Only removes the overrides of this semver requirement"))))
         .get_matches();
 
 
@@ -147,6 +408,13 @@ pub fn main() {
             docbuilder_opts.skip_if_log_exists = matches.is_present("SKIP_IF_LOG_EXISTS");
             docbuilder_opts.keep_build_directory = matches.is_present("KEEP_BUILD_DIRECTORY");
 
+            if let Some(matches) = matches.subcommand_matches("world") {
+                docbuilder_opts.build_only_latest_version =
+                    matches.is_present("ONLY_LATEST_VERSION");
+                docbuilder_opts.skip_oldest_versions = matches.is_present("SKIP_OLDEST_VERSIONS");
+                docbuilder_opts.debug = matches.is_present("DEBUG");
+            }
+
             docbuilder_opts.check_paths().unwrap();
 
             docbuilder_opts
@@ -154,11 +422,48 @@ pub fn main() {
 
         let mut docbuilder = DocBuilder::new(docbuilder_opts);
 
-        if let Some(_) = matches.subcommand_matches("world") {
+        if let Some(matches) = matches.subcommand_matches("world") {
+            let filter = WorldFilter {
+                name_glob: matches.value_of("NAME").map(|glob| glob.to_owned()),
+                crates: matches.value_of("CRATES_FILE").map(read_crates_file),
+                published_after: matches.value_of("PUBLISHED_AFTER").map(|date| {
+                    time::strptime(date, "%Y-%m-%d")
+                        .expect("--published-after was not a YYYY-MM-DD date")
+                        .to_timespec()
+                }),
+                limit: matches.value_of("LIMIT")
+                    .map(|limit| limit.parse().expect("--limit was not a number")),
+            };
             docbuilder.load_cache().expect("Failed to load cache");
             let mut builder = RustwideBuilder::init().unwrap();
-            builder.build_world(&mut docbuilder).expect("Failed to build world");
+            builder.build_world(&mut docbuilder, &filter, matches.is_present("RESTART"))
+                .expect("Failed to build world");
             docbuilder.save_cache().expect("Failed to save cache");
+        } else if let Some(matches) = matches.subcommand_matches("world-status") {
+            let conn = connect_db().expect("Could not connect to database");
+            let run = get_world_run(&conn).expect("Could not get build world progress");
+            match run {
+                Some(ref run) if matches.is_present("JSON") => println!("{}", run.to_json()),
+                Some(run) => {
+                    println!("Run {} ({}), started at {}",
+                             run.id,
+                             run.description,
+                             time::at(run.started_at).rfc822());
+                    println!("{} releases: {} built, {} failed, {} skipped, {} remaining",
+                             run.total,
+                             run.built,
+                             run.failed,
+                             run.skipped,
+                             run.remaining());
+                    if let Some(finished_at) = run.finished_at {
+                        println!("Finished at {}", time::at(finished_at).rfc822());
+                    } else if let Some(eta) = run.eta() {
+                        println!("ETA: {}h {}m", eta.num_hours(), eta.num_minutes() % 60);
+                    }
+                }
+                None if matches.is_present("JSON") => println!("null"),
+                None => println!("No build world run"),
+            }
         } else if let Some(matches) = matches.subcommand_matches("crate") {
             docbuilder.load_cache().expect("Failed to load cache");
             let mut builder = RustwideBuilder::init().unwrap();
@@ -166,9 +471,98 @@ pub fn main() {
                                   matches.value_of("CRATE_VERSION").unwrap())
                 .expect("Building documentation failed");
             docbuilder.save_cache().expect("Failed to save cache");
+        } else if let Some(matches) = matches.subcommand_matches("local") {
+            // the documentation of published crates is never overwritten
+            let destination = TempDir::new("cratesfyi-local-docs")
+                .expect("Failed to create destination");
+            let mut options = docbuilder.options().clone();
+            options.destination = destination.path().to_owned();
+            let local_docbuilder = DocBuilder::new(options);
+
+            let storage = if matches.is_present("SERVE") {
+                let storage_dir = TempDir::new("cratesfyi-local")
+                    .expect("Failed to create storage");
+                let storage: Arc<dyn storage::Storage> =
+                    Arc::new(storage::LocalStorage::new(storage_dir.path()));
+                Some((storage_dir, storage))
+            } else {
+                None
+            };
+
+            let mut builder = RustwideBuilder::init_local().unwrap();
+            let build = builder.build_local(&local_docbuilder,
+                                            Path::new(matches.value_of("PATH").unwrap()),
+                                            storage.as_ref().map(|&(_, ref storage)| &**storage))
+                .expect("Building documentation failed");
+            if !build.successful() {
+                println!("{}", build.build_log());
+                println!("Building documentation of {} {} failed ({})",
+                         build.name,
+                         build.version,
+                         build.failure_kind().map(|kind| kind.description()).unwrap_or("unknown"));
+                // process::exit doesn't remove the temporary directories
+                drop(storage);
+                drop(destination);
+                process::exit(1);
+            }
+
+            println!("Documentation of {} {} built for {}{}",
+                     build.name,
+                     build.version,
+                     build.default_target,
+                     build.successful_targets.iter()
+                         .map(|target| format!(", {}", target))
+                         .collect::<String>());
+            if !build.has_docs {
+                println!("No documentation was generated, is {} a library?", build.name);
+            } else if let Some((_storage_dir, storage)) = storage {
+                let addr = matches.value_of("SOCKET_ADDR").unwrap_or("localhost:3000");
+                println!("Serving documentation at http://{}/rustdoc/{}/{}/{}/index.html",
+                         addr,
+                         build.name,
+                         build.version,
+                         build.name.replace("-", "_"));
+                start_preview_server(Some(addr), storage);
+            } else {
+                // kept after exiting, so the documentation can be browsed
+                let destination = destination.into_path().join(&build.name).join(&build.version);
+                println!("Documentation copied into {}", destination.display());
+            }
         } else if let Some(_) = matches.subcommand_matches("add-essential-files") {
             let mut builder = RustwideBuilder::init().unwrap();
             builder.add_essential_files().expect("failed to add essential files");
+        } else if let Some(matches) = matches.subcommand_matches("set-toolchain") {
+            let mut builder = RustwideBuilder::init().unwrap();
+            builder.set_toolchain(matches.value_of("TOOLCHAIN").unwrap())
+                .expect("failed to set toolchain");
+        } else if let Some(matches) = matches.subcommand_matches("add-toolchain") {
+            let mut builder = RustwideBuilder::init().unwrap();
+            builder.add_toolchain(matches.value_of("TOOLCHAIN").unwrap())
+                .expect("failed to add toolchain");
+        } else if let Some(matches) = matches.subcommand_matches("remove-toolchain") {
+            let builder = RustwideBuilder::init().unwrap();
+            builder.remove_toolchain(matches.value_of("TOOLCHAIN").unwrap())
+                .expect("failed to remove toolchain");
+        } else if let Some(_) = matches.subcommand_matches("list-toolchains") {
+            let builder = RustwideBuilder::init().unwrap();
+            let (default, extra) = builder.toolchains().expect("failed to get toolchains");
+            println!("{} (default)", default);
+            for toolchain in extra {
+                println!("{}", toolchain);
+            }
+        } else if let Some(matches) = matches.subcommand_matches("add-system-dependency") {
+            let builder = RustwideBuilder::init().unwrap();
+            builder.add_system_dependency(matches.value_of("PACKAGE").unwrap())
+                .expect("failed to add system dependency");
+        } else if let Some(matches) = matches.subcommand_matches("remove-system-dependency") {
+            let builder = RustwideBuilder::init().unwrap();
+            builder.remove_system_dependency(matches.value_of("PACKAGE").unwrap())
+                .expect("failed to remove system dependency");
+        } else if let Some(_) = matches.subcommand_matches("list-system-dependencies") {
+            let builder = RustwideBuilder::init().unwrap();
+            for package in builder.system_dependencies().expect("failed to get dependencies") {
+                println!("{}", package);
+            }
         } else if let Some(_) = matches.subcommand_matches("lock") {
             docbuilder.lock().expect("Failed to lock");
         } else if let Some(_) = matches.subcommand_matches("unlock") {
@@ -185,7 +579,8 @@ pub fn main() {
         } else if let Some(_) = matches.subcommand_matches("update-github-fields") {
             cratesfyi::utils::github_updater().expect("Failed to update github fields");
         } else if let Some(matches) = matches.subcommand_matches("add-directory") {
-            add_path_into_database(&db::connect_db().unwrap(),
+            let storage = storage::from_env().expect("Failed to initialize file storage");
+            add_path_into_database(&*storage,
                                    matches.value_of("PREFIX").unwrap_or(""),
                                    matches.value_of("DIRECTORY").unwrap())
                 .expect("Failed to add directory into database");
@@ -195,6 +590,16 @@ pub fn main() {
         } else if let Some(_) = matches.subcommand_matches("update-search-index") {
             let conn = db::connect_db().unwrap();
             db::update_search_index(&conn).expect("Failed to update search index");
+        } else if let Some(matches) = matches.subcommand_matches("delete-crate") {
+            let conn = db::connect_db().expect("Could not connect to database");
+            let storage = storage::from_env().expect("Failed to initialize file storage");
+            let name = matches.value_of("CRATE_NAME").unwrap();
+            if let Some(version) = matches.value_of("CRATE_VERSION") {
+                db::delete_version(&conn, &*storage, name, version)
+                    .expect("Failed to delete release");
+            } else {
+                db::delete_crate(&conn, &*storage, name).expect("Failed to delete crate");
+            }
         } else if let Some(_) = matches.subcommand_matches("move-to-s3") {
             let conn = db::connect_db().unwrap();
             let mut count = 1;
@@ -212,6 +617,17 @@ pub fn main() {
         start_web_server(Some(matches.value_of("SOCKET_ADDR").unwrap_or("0.0.0.0:3000")));
     } else if let Some(_) = matches.subcommand_matches("daemon") {
         cratesfyi::utils::start_daemon();
+    } else if let Some(matches) = matches.subcommand_matches("check-metadata") {
+        let manifest = matches.value_of("MANIFEST").unwrap();
+        let (_, warnings) = Metadata::check_manifest(manifest)
+            .unwrap_or_else(|e| panic!("Failed to read {}: {}", manifest, e));
+        for warning in &warnings {
+            println!("warning: {}", warning);
+        }
+        if !warnings.is_empty() {
+            process::exit(1);
+        }
+        println!("{}: no problems found", manifest);
     } else if let Some(matches) = matches.subcommand_matches("queue") {
         if let Some(matches) = matches.subcommand_matches("add") {
             let priority = matches.value_of("BUILD_PRIORITY").unwrap_or("5");
@@ -222,6 +638,159 @@ pub fn main() {
                                matches.value_of("CRATE_NAME").unwrap(),
                                matches.value_of("CRATE_VERSION").unwrap(),
                                priority).expect("Could not add crate to queue");
+        } else if let Some(matches) = matches.subcommand_matches("list") {
+            let conn = connect_db().expect("Could not connect to database");
+            let crates = get_queued_crates(&conn, matches.is_present("FAILED"))
+                .expect("Could not get the build queue");
+
+            if matches.is_present("JSON") {
+                println!("{}", crates.to_json());
+            } else {
+                for krate in &crates {
+                    println!("{:>6} {}-{} (priority: {}, attempts: {}{}{})",
+                             krate.id,
+                             krate.name,
+                             krate.version,
+                             krate.priority,
+                             krate.attempt,
+                             if krate.is_failed() { ", failed" } else { "" },
+                             krate.claimed_by.as_ref()
+                                 .map(|worker| format!(", building on {}", worker))
+                                 .unwrap_or_default());
+                    if let Some(ref error) = krate.last_error {
+                        println!("       last error: {}", error.lines().next().unwrap_or(""));
+                    }
+                }
+            }
+        } else if let Some(matches) = matches.subcommand_matches("remove") {
+            let conn = connect_db().expect("Could not connect to database");
+            let count = remove_crate_from_queue(&conn,
+                                                matches.value_of("CRATE_NAME").unwrap(),
+                                                matches.value_of("CRATE_VERSION"))
+                .expect("Could not remove crate from queue");
+            print_queue_update(matches.is_present("JSON"), "removed", count);
+        } else if let Some(matches) = matches.subcommand_matches("set-priority") {
+            let priority: i32 = matches.value_of("BUILD_PRIORITY").unwrap()
+                .parse().expect("priority was not a number");
+            let conn = connect_db().expect("Could not connect to database");
+            let count = set_queue_priority(&conn,
+                                           matches.value_of("CRATE_NAME").unwrap(),
+                                           matches.value_of("CRATE_VERSION"),
+                                           priority)
+                .expect("Could not change priority");
+            print_queue_update(matches.is_present("JSON"), "updated", count);
+        } else if let Some(matches) = matches.subcommand_matches("rebuild") {
+            let selector = RebuildSelector {
+                rustc_before: matches.value_of("RUSTC_BEFORE").map(|v| v.to_owned()),
+                built_before: matches.value_of("BUILT_BEFORE").map(|date| {
+                    time::strptime(date, "%Y-%m-%d")
+                        .expect("--built-before was not a YYYY-MM-DD date")
+                        .to_timespec()
+                }),
+                failed_only: matches.is_present("FAILED"),
+                latest_only: matches.is_present("LATEST"),
+                top: matches.value_of("TOP")
+                    .map(|top| top.parse().expect("--top was not a number")),
+                crates: matches.value_of("CRATES_FILE").map(read_crates_file),
+            };
+            let priority: i32 = matches.value_of("BUILD_PRIORITY")
+                .map(|p| p.parse().expect("--priority was not a number"))
+                .unwrap_or(REBUILD_PRIORITY);
+            let conn = connect_db().expect("Could not connect to database");
+
+            if matches.is_present("DRY_RUN") {
+                let releases = selector.select_releases(&conn)
+                    .expect("Could not select releases");
+                for &(ref name, ref version) in &releases {
+                    println!("{}-{}", name, version);
+                }
+                println!("{} releases selected ({})", releases.len(), selector.describe());
+            } else {
+                let (id, count) = start_rebuild_campaign(&conn,
+                                                         &selector,
+                                                         matches.value_of("DESCRIPTION"),
+                                                         priority)
+                    .expect("Could not start rebuild campaign");
+                println!("Rebuild campaign {} queued {} releases", id, count);
+            }
+        } else if let Some(matches) = matches.subcommand_matches("campaigns") {
+            let conn = connect_db().expect("Could not connect to database");
+            let campaigns = get_rebuild_campaigns(&conn).expect("Could not get campaigns");
+
+            if matches.is_present("JSON") {
+                println!("{}", campaigns.to_json());
+            } else {
+                for campaign in &campaigns {
+                    println!("{:>4} {} ({}): {}/{} built, {} failed",
+                             campaign.id,
+                             campaign.description,
+                             time::at(campaign.created_at).strftime("%Y-%m-%d").unwrap(),
+                             campaign.built,
+                             campaign.total,
+                             campaign.failed);
+                }
+            }
+        } else if let Some(matches) = matches.subcommand_matches("retry-failed") {
+            let conn = connect_db().expect("Could not connect to database");
+            let count = retry_failed_crates(&conn, matches.value_of("CRATE_NAME"))
+                .expect("Could not reset failed crates");
+            print_queue_update(matches.is_present("JSON"), "updated", count);
+        }
+    } else if let Some(matches) = matches.subcommand_matches("overrides") {
+        let conn = connect_db().expect("Could not connect to database");
+
+        if let Some(matches) = matches.subcommand_matches("list") {
+            let overrides = BuildOverrides::list(&conn, matches.value_of("CRATE_NAME"))
+                .expect("Could not get overrides");
+            if matches.is_present("JSON") {
+                println!("{}", overrides.to_json());
+            } else {
+                for overrides in &overrides {
+                    println!("{}", format_overrides(overrides));
+                }
+            }
+        } else if let Some(matches) = matches.subcommand_matches("set") {
+            let name = matches.value_of("CRATE_NAME").unwrap();
+            let version_req = matches.value_of("VERSION_REQ").unwrap_or("*");
+            let mut overrides = if matches.is_present("RESET") {
+                None
+            } else {
+                BuildOverrides::get(&conn, name, version_req).expect("Could not get overrides")
+            }.unwrap_or_else(|| BuildOverrides::new(name, version_req));
+
+            if let Some(memory) = matches.value_of("MEMORY") {
+                overrides.max_memory_bytes =
+                    Some(memory.parse().expect("--memory was not a number"));
+            }
+            if let Some(timeout) = matches.value_of("TIMEOUT") {
+                overrides.timeout_seconds =
+                    Some(timeout.parse().expect("--timeout was not a number"));
+            }
+            if let Some(networking) = matches.value_of("NETWORKING") {
+                overrides.networking = Some(networking == "allowed");
+            }
+            if let Some(size) = matches.value_of("MAX_LOG_SIZE") {
+                overrides.max_log_size =
+                    Some(size.parse().expect("--max-log-size was not a number"));
+            }
+            if let Some(targets) = matches.values_of("TARGETS") {
+                overrides.extra_targets = Some(targets.map(|t| t.to_owned()).collect());
+            }
+            if let Some(features) = matches.values_of("FEATURES") {
+                overrides.features = Some(features.map(|f| f.to_owned()).collect());
+            }
+            if let Some(toolchain) = matches.value_of("TOOLCHAIN") {
+                overrides.toolchain = Some(toolchain.to_owned());
+            }
+
+            overrides.save(&conn).expect("Could not save overrides");
+            println!("{}", format_overrides(&overrides));
+        } else if let Some(matches) = matches.subcommand_matches("remove") {
+            let count = BuildOverrides::remove(&conn,
+                                               matches.value_of("CRATE_NAME").unwrap(),
+                                               matches.value_of("VERSION_REQ"))
+                .expect("Could not remove overrides");
+            println!("removed {} overrides", count);
         }
     } else {
         println!("{}", matches.usage());
@@ -230,6 +799,64 @@ pub fn main() {
 
 
 
+/// Reads a list of crate names, one per line. Empty lines and comments are skipped.
+fn read_crates_file(path: &str) -> Vec<String> {
+    let mut content = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut content))
+        .expect("Could not read crates file");
+    content.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_owned())
+        .collect()
+}
+
+
+/// Prints the number of queue rows changed by a command
+fn print_queue_update(json: bool, action: &str, count: u64) {
+    if json {
+        let mut m = ::std::collections::BTreeMap::new();
+        m.insert(action.to_owned(), count);
+        println!("{}", m.to_json());
+    } else {
+        println!("{} {} crates in the queue", action, count);
+    }
+}
+
+
+/// Formats build overrides on one line, with only the fields they set
+fn format_overrides(overrides: &BuildOverrides) -> String {
+    let mut fields = Vec::new();
+    if let Some(memory) = overrides.max_memory_bytes {
+        fields.push(format!("memory: {} bytes", memory));
+    }
+    if let Some(timeout) = overrides.timeout_seconds {
+        fields.push(format!("timeout: {}s", timeout));
+    }
+    if let Some(networking) = overrides.networking {
+        fields.push(format!("networking: {}",
+                            if networking { "allowed" } else { "blocked" }));
+    }
+    if let Some(size) = overrides.max_log_size {
+        fields.push(format!("max log size: {} bytes", size));
+    }
+    if let Some(ref targets) = overrides.extra_targets {
+        fields.push(format!("extra targets: {}", targets.join(" ")));
+    }
+    if let Some(ref features) = overrides.features {
+        fields.push(format!("features: {}", features.join(" ")));
+    }
+    if let Some(ref toolchain) = overrides.toolchain {
+        fields.push(format!("toolchain: {}", toolchain));
+    }
+    if fields.is_empty() {
+        fields.push("no overrides".to_owned());
+    }
+    format!("{} {} ({})", overrides.crate_name, overrides.version_req, fields.join(", "))
+}
+
+
 fn logger_init() {
     use std::io::Write;
 
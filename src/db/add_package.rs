@@ -1,7 +1,7 @@
 
 use Metadata;
 use utils::MetadataPackage;
-use docbuilder::BuildResult;
+use docbuilder::{BuildResult, BuildSteps};
 use regex::Regex;
 
 use std::io::prelude::*;
@@ -28,6 +28,7 @@ pub(crate) fn add_package_into_database(conn: &Connection,
                                  res: &BuildResult,
                                  files: Option<Json>,
                                  doc_targets: Vec<String>,
+                                 identical_doc_targets: Vec<String>,
                                  has_docs: bool,
                                  has_examples: bool)
                                  -> Result<i32> {
@@ -55,11 +56,12 @@ pub(crate) fn add_package_into_database(conn: &Connection,
                                             homepage_url, description, description_long, readme,
                                             authors, keywords, have_examples, downloads, files,
                                             doc_targets, is_library, doc_rustc_version,
-                                            documentation_url, default_target
+                                            documentation_url, default_target,
+                                            identical_doc_targets
                                         )
                                         VALUES ( $1,  $2,  $3,  $4, $5, $6,  $7, $8, $9, $10,
                                                  $11, $12, $13, $14, $15, $16, $17, $18, $19,
-                                                 $20, $21, $22, $23, $24, $25
+                                                 $20, $21, $22, $23, $24, $25, $26
                                         )
                                         RETURNING id",
                                        &[&crate_id,
@@ -86,7 +88,8 @@ pub(crate) fn add_package_into_database(conn: &Connection,
                                          &is_library,
                                          &res.rustc_version,
                                          &metadata_pkg.documentation,
-                                         &metadata.default_target]));
+                                         &metadata.default_target,
+                                         &identical_doc_targets.to_json()]));
             // return id
             rows.get(0).get(0)
 
@@ -114,7 +117,8 @@ pub(crate) fn add_package_into_database(conn: &Connection,
                                  is_library = $22,
                                  doc_rustc_version = $23,
                                  documentation_url = $24,
-                                 default_target = $25
+                                 default_target = $25,
+                                 identical_doc_targets = $26
                              WHERE crate_id = $1 AND version = $2",
                             &[&crate_id,
                               &format!("{}", metadata_pkg.version),
@@ -140,7 +144,8 @@ pub(crate) fn add_package_into_database(conn: &Connection,
                               &is_library,
                               &res.rustc_version,
                               &metadata_pkg.documentation,
-                              &metadata.default_target]));
+                              &metadata.default_target,
+                              &identical_doc_targets.to_json()]));
             rows.get(0).get(0)
         }
     };
@@ -186,18 +191,40 @@ pub(crate) fn add_build_into_database(conn: &Connection,
     debug!("Adding build into database");
     let rows = try!(conn.query("INSERT INTO builds (rid, rustc_version,
                                                     cratesfyi_version,
-                                                    build_status, output)
-                                VALUES ($1, $2, $3, $4, $5)
+                                                    build_status, failure_kind, output)
+                                VALUES ($1, $2, $3, $4, $5, $6)
                                 RETURNING id",
                                &[release_id,
                                  &res.rustc_version,
                                  &res.docsrs_version,
                                  &res.successful,
+                                 &res.failure_kind.map(|kind| kind.as_str()),
                                  &res.build_log]));
     Ok(rows.get(0).get(0))
 }
 
 
+/// Adds the steps of a build into database
+pub(crate) fn add_build_steps_into_database(conn: &Connection,
+                                            build_id: i32,
+                                            steps: &BuildSteps)
+                                            -> Result<()> {
+    for (position, step) in steps.steps().iter().enumerate() {
+        try!(conn.execute("INSERT INTO build_steps (build_id, position, name, duration_ms,
+                                                    successful, error, output)
+                           VALUES ($1, $2, $3, $4, $5, $6, $7)",
+                          &[&build_id,
+                            &(position as i32),
+                            &step.name,
+                            &step.duration_ms(),
+                            &step.successful(),
+                            &step.error,
+                            &step.output]));
+    }
+    Ok(())
+}
+
+
 fn initialize_package_in_database(conn: &Connection, pkg: &MetadataPackage) -> Result<i32> {
     let mut rows = try!(conn.query("SELECT id FROM crates WHERE name = $1", &[&pkg.name]));
     // insert crate into database if it is not exists
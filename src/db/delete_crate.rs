@@ -0,0 +1,149 @@
+//! Removal of crates and releases, used for takedowns and crates published by mistake.
+
+use super::update_search_index;
+use error::Result;
+use postgres::Connection;
+use postgres::transaction::Transaction;
+use rustc_serialize::json::{Json, ToJson};
+use storage::Storage;
+
+
+/// Prefixes of the files stored for every release
+const STORAGE_PREFIXES: &'static [&'static str] = &["rustdoc", "sources", "downloads"];
+
+
+/// Deletes a crate with all of its releases and their files.
+pub fn delete_crate(conn: &Connection, storage: &dyn Storage, name: &str) -> Result<()> {
+    let crate_id = match get_crate_id(conn, name)? {
+        Some(id) => id,
+        None => bail!("crate {} doesn't exist", name),
+    };
+
+    let trans = conn.transaction()?;
+    let release_ids: Vec<i32> = trans.query("SELECT id FROM releases WHERE crate_id = $1",
+                                            &[&crate_id])?
+        .iter()
+        .map(|row| row.get(0))
+        .collect();
+    for release_id in &release_ids {
+        delete_release_rows(&trans, *release_id)?;
+    }
+    trans.execute("DELETE FROM owner_rels WHERE cid = $1", &[&crate_id])?;
+    trans.execute("DELETE FROM crates WHERE id = $1", &[&crate_id])?;
+    // pending builds would add the releases back
+    trans.execute("DELETE FROM queue WHERE name = $1", &[&name])?;
+    trans.execute("DELETE FROM build_world_releases WHERE name = $1", &[&name])?;
+    trans.commit()?;
+
+    // Files are deleted once nothing points to them anymore
+    for prefix in STORAGE_PREFIXES {
+        storage.delete_prefix(&format!("{}/{}/", prefix, name))?;
+    }
+
+    info!("deleted crate {} with {} releases", name, release_ids.len());
+    Ok(())
+}
+
+
+/// Deletes a single release of a crate and its files.
+///
+/// The crate is deleted too if this was its only release.
+pub fn delete_version(conn: &Connection,
+                      storage: &dyn Storage,
+                      name: &str,
+                      version: &str)
+                      -> Result<()> {
+    let crate_id = match get_crate_id(conn, name)? {
+        Some(id) => id,
+        None => bail!("crate {} doesn't exist", name),
+    };
+    let rows = conn.query("SELECT id FROM releases WHERE crate_id = $1 AND version = $2",
+                          &[&crate_id, &version])?;
+    if rows.is_empty() {
+        bail!("release {} {} doesn't exist", name, version);
+    }
+    let release_id: i32 = rows.get(0).get(0);
+
+    let trans = conn.transaction()?;
+    delete_release_rows(&trans, release_id)?;
+    // pending builds would add the release back
+    trans.execute("DELETE FROM queue WHERE name = $1 AND version = $2", &[&name, &version])?;
+    trans.execute("DELETE FROM build_world_releases WHERE name = $1 AND version = $2",
+                  &[&name, &version])?;
+
+    let remaining: i64 = trans.query("SELECT COUNT(*) FROM releases WHERE crate_id = $1",
+                                     &[&crate_id])?
+        .get(0)
+        .get(0);
+    if remaining == 0 {
+        trans.execute("DELETE FROM owner_rels WHERE cid = $1", &[&crate_id])?;
+        trans.execute("DELETE FROM crates WHERE id = $1", &[&crate_id])?;
+    } else {
+        let versions: Json = trans.query("SELECT versions FROM crates WHERE id = $1",
+                                         &[&crate_id])?
+            .get(0)
+            .get(0);
+        let versions = remove_version(&versions, version);
+        // the search index update picks the new latest release
+        trans.execute("UPDATE crates
+                       SET versions = $1,
+                           latest_version_id = CASE WHEN latest_version_id = $2 THEN 0
+                                                    ELSE latest_version_id END
+                       WHERE id = $3",
+                      &[&versions, &release_id, &crate_id])?;
+        update_search_index(&trans)?;
+    }
+    trans.commit()?;
+
+    // Files are deleted once nothing points to them anymore
+    for prefix in STORAGE_PREFIXES {
+        storage.delete_prefix(&format!("{}/{}/{}/", prefix, name, version))?;
+    }
+
+    info!("deleted release {} {}", name, version);
+    Ok(())
+}
+
+
+fn get_crate_id(conn: &Connection, name: &str) -> Result<Option<i32>> {
+    let rows = conn.query("SELECT id FROM crates WHERE name = $1", &[&name])?;
+    Ok(if rows.is_empty() { None } else { Some(rows.get(0).get(0)) })
+}
+
+
+fn delete_release_rows(trans: &Transaction, release_id: i32) -> Result<()> {
+    for table in &["builds", "author_rels", "keyword_rels"] {
+        trans.execute(&format!("DELETE FROM {} WHERE rid = $1", table), &[&release_id])?;
+    }
+    trans.execute("DELETE FROM releases WHERE id = $1", &[&release_id])?;
+    Ok(())
+}
+
+
+/// Removes `version` from the `crates.versions` JSON array
+fn remove_version(versions: &Json, version: &str) -> Json {
+    versions.as_array()
+        .map(|versions| {
+            versions.iter()
+                .filter(|v| v.as_string() != Some(version))
+                .cloned()
+                .collect::<Vec<Json>>()
+        })
+        .unwrap_or_default()
+        .to_json()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::remove_version;
+    use rustc_serialize::json::Json;
+
+    #[test]
+    fn test_remove_version() {
+        let versions = Json::from_str(r#"["0.1.0", "0.2.0", "0.2.0-beta"]"#).unwrap();
+        assert_eq!(remove_version(&versions, "0.2.0"),
+                   Json::from_str(r#"["0.1.0", "0.2.0-beta"]"#).unwrap());
+        assert_eq!(remove_version(&versions, "1.0.0"), versions);
+    }
+}
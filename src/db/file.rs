@@ -1,8 +1,7 @@
 //! Simple module to store files in database.
 //!
-//! cratesfyi is generating more than 5 million files, they are small and mostly html files.
-//! They are using so many inodes and it is better to store them in database instead of
-//! filesystem. This module is adding files into database and retrieving them.
+//! This module is collecting files from a directory and adding them into the configured
+//! `Storage` backend.
 
 
 use std::path::{PathBuf, Path};
@@ -12,9 +11,14 @@ use std::fs;
 use std::io::Read;
 use error::Result;
 use failure::err_msg;
-use rusoto_s3::{S3, PutObjectRequest, GetObjectRequest, S3Client};
-use rusoto_core::region::Region;
-use rusoto_credential::DefaultCredentialsProvider;
+use rusoto_s3::{S3, PutObjectRequest};
+use storage::{compress, is_compressible, Blob, S3Storage, Storage, GZIP};
+use time;
+use magic::{Cookie, flags};
+
+
+/// Number of files read and stored at once by `add_path_into_database`
+const UPLOAD_BATCH_SIZE: usize = 500;
 
 
 fn get_file_list_from_dir<P: AsRef<Path>>(path: P,
@@ -55,197 +59,112 @@ pub fn get_file_list<P: AsRef<Path>>(path: P) -> Result<Vec<PathBuf>> {
     Ok(files)
 }
 
-pub struct Blob {
-    pub path: String,
-    pub mime: String,
-    pub date_updated: time::Timespec,
-    pub content: Vec<u8>,
-}
-
-pub fn get_path(conn: &Connection, path: &str) -> Option<Blob> {
-    if let Some(client) = s3_client() {
-        let res = client.get_object(GetObjectRequest {
-            bucket: "rust-docs-rs".into(),
-            key: path.into(),
-            ..Default::default()
-        }).sync();
-
-        let res = match res {
-            Ok(r) => r,
-            Err(_) => {
-                return None;
-            }
-        };
+/// Adds files into the storage and returns list of files with their mime type in Json
+pub fn add_path_into_database<P: AsRef<Path>>(storage: &dyn Storage,
+                                              prefix: &str,
+                                              path: P)
+                                              -> Result<Json> {
+    let cookie = try!(mime_cookie());
+    let mut file_list_with_mimes: Vec<(String, PathBuf)> = Vec::new();
 
-        let mut b = res.body.unwrap().into_blocking_read();
-        let mut content = Vec::new();
-        b.read_to_end(&mut content).unwrap();
-
-        let last_modified = res.last_modified.unwrap();
-        let last_modified = time::strptime(&last_modified, "%a, %d %b %Y %H:%M:%S %Z")
-            .unwrap_or_else(|e| panic!("failed to parse {:?} as timespec: {:?}", last_modified, e))
-            .to_timespec();
-
-        Some(Blob {
-            path: path.into(),
-            mime: res.content_type.unwrap(),
-            date_updated: last_modified,
-            content,
-        })
-    } else {
-        let rows = conn.query("SELECT path, mime, date_updated, content
-                            FROM files
-                            WHERE path = $1", &[&path]).unwrap();
-
-        if rows.len() == 0 {
-            None
-        } else {
-            let row = rows.get(0);
-
-            Some(Blob {
-                path: row.get(0),
-                mime: row.get(1),
-                date_updated: row.get(2),
-                content: row.get(3),
-            })
-        }
+    // Files are stored in batches to avoid keeping the content of big crates in memory
+    for file_list in try!(get_file_list(&path)).chunks(UPLOAD_BATCH_SIZE) {
+        let (blobs, mut mimes) = try!(collect_blobs(&cookie, prefix, &path, file_list));
+        try!(storage.store(&blobs));
+        file_list_with_mimes.append(&mut mimes);
     }
+
+    file_list_to_json(file_list_with_mimes)
 }
 
-fn s3_client() -> Option<S3Client> {
-    // If AWS keys aren't configured, then presume we should use the DB exclusively
-    // for file storage.
-    if std::env::var_os("AWS_ACCESS_KEY_ID").is_none() && std::env::var_os("FORCE_S3").is_none() {
-        return None;
+
+/// Adds files into the storage as a single archive named after `prefix`, and returns list of
+/// files with their mime type in Json
+///
+/// Backends without archive support store the files in batches, like `add_path_into_database`.
+pub fn add_path_into_archive<P: AsRef<Path>>(storage: &dyn Storage,
+                                             prefix: &str,
+                                             path: P)
+                                             -> Result<Json> {
+    if !storage.supports_archives() {
+        return add_path_into_database(storage, prefix, path);
     }
-    let creds = match DefaultCredentialsProvider::new() {
-        Ok(creds) => creds,
-        Err(err) => {
-            warn!("failed to retrieve AWS credentials: {}", err);
-            return None;
-        }
-    };
-    Some(S3Client::new_with(
-        rusoto_core::request::HttpClient::new().unwrap(),
-        creds,
-        std::env::var("S3_ENDPOINT").ok().map(|e| Region::Custom {
-            name: "us-west-1".to_owned(),
-            endpoint: e,
-        }).unwrap_or(Region::UsWest1),
-    ))
+
+    let cookie = try!(mime_cookie());
+    let file_list = try!(get_file_list(&path));
+    let (blobs, file_list_with_mimes) = try!(collect_blobs(&cookie, prefix, &path, &file_list));
+    try!(storage.store_archive(&format!("archives/{}.tar", prefix), &blobs));
+    file_list_to_json(file_list_with_mimes)
 }
 
-/// Adds files into database and returns list of files with their mime type in Json
-pub fn add_path_into_database<P: AsRef<Path>>(conn: &Connection,
-                                              prefix: &str,
-                                              path: P)
-                                              -> Result<Json> {
-    use magic::{Cookie, flags};
+
+fn mime_cookie() -> Result<Cookie> {
     let cookie = try!(Cookie::open(flags::MIME_TYPE));
     try!(cookie.load::<&str>(&[]));
+    Ok(cookie)
+}
 
-    let trans = try!(conn.transaction());
-    let mut client = s3_client();
+
+/// Reads the files of `file_list`, relative to `path`, into blobs
+fn collect_blobs<P: AsRef<Path>>(cookie: &Cookie,
+                                 prefix: &str,
+                                 path: P,
+                                 file_list: &[PathBuf])
+                                 -> Result<(Vec<Blob>, Vec<(String, PathBuf)>)> {
+    let mut blobs: Vec<Blob> = Vec::new();
     let mut file_list_with_mimes: Vec<(String, PathBuf)> = Vec::new();
 
-    for file_path in try!(get_file_list(&path)) {
-        let (path, content, mime) = {
-            let path = Path::new(path.as_ref()).join(&file_path);
-            // Some files have insufficient permissions (like .lock file created by cargo in
-            // documentation directory). We are skipping this files.
-            let mut file = match fs::File::open(path) {
-                Ok(f) => f,
-                Err(_) => continue,
-            };
-            let mut content: Vec<u8> = Vec::new();
-            try!(file.read_to_end(&mut content));
-            let bucket_path = Path::new(prefix).join(&file_path)
-                .into_os_string().into_string().unwrap();
-
-            let mime = {
-                let mime = try!(cookie.buffer(&content));
-                // css's are causing some problem in browsers
-                // magic will return text/plain for css file types
-                // convert them to text/css
-                // do the same for javascript files
-                if mime == "text/plain" {
-                    let e = file_path.extension().unwrap_or_default();
-                    if e == "css" {
-                        "text/css".to_owned()
-                    } else if e == "js" {
-                        "application/javascript".to_owned()
-                    } else {
-                        mime.to_owned()
-                    }
+    for file_path in file_list {
+        let path = Path::new(path.as_ref()).join(&file_path);
+        // Some files have insufficient permissions (like .lock file created by cargo in
+        // documentation directory). We are skipping this files.
+        let mut file = match fs::File::open(path) {
+            Ok(f) => f,
+            Err(_) => continue,
+        };
+        let mut content: Vec<u8> = Vec::new();
+        try!(file.read_to_end(&mut content));
+        let bucket_path = Path::new(prefix).join(&file_path)
+            .into_os_string().into_string().unwrap();
+
+        let mime = {
+            let mime = try!(cookie.buffer(&content));
+            // css's are causing some problem in browsers
+            // magic will return text/plain for css file types
+            // convert them to text/css
+            // do the same for javascript files
+            if mime == "text/plain" {
+                let e = file_path.extension().unwrap_or_default();
+                if e == "css" {
+                    "text/css".to_owned()
+                } else if e == "js" {
+                    "application/javascript".to_owned()
                 } else {
                     mime.to_owned()
                 }
-            };
-
-            let content: Option<Vec<u8>> = if let Some(client) = &mut client {
-                let mut attempts = 0;
-                loop {
-                    let s3_res = client.put_object(PutObjectRequest {
-                        bucket: "rust-docs-rs".into(),
-                        key: bucket_path.clone(),
-                        body: Some(content.clone().into()),
-                        content_type: Some(mime.clone()),
-                        ..Default::default()
-                    }).sync();
-                    attempts += 1;
-                    match s3_res {
-                        // we've successfully uploaded the content, so steal it;
-                        // we don't want to put it in the DB
-                        Ok(_) => break None,
-                        // Since s3 was configured, we want to panic on failure to upload.
-                        Err(e) => {
-                            log::error!("failed to upload to {}: {:?}", bucket_path, e);
-                            // Get a new client, in case the old one's connection is stale.
-                            // AWS will kill our connection if it's alive for too long; this avoids
-                            // that preventing us from building the crate entirely.
-                            *client = s3_client().unwrap();
-                            if attempts > 3 {
-                                panic!("failed to upload 3 times, exiting");
-                            } else {
-                                continue;
-                            }
-                        },
-                    }
-                }
             } else {
-                Some(content.clone().into())
-            };
-
-            file_list_with_mimes.push((mime.clone(), file_path.clone()));
-
-            (
-                bucket_path,
-                content,
-                mime,
-            )
+                mime.to_owned()
+            }
         };
 
-        // If AWS credentials are configured, don't insert/update the database
-        if client.is_none() {
-            // check if file already exists in database
-            let rows = try!(conn.query("SELECT COUNT(*) FROM files WHERE path = $1", &[&path]));
+        file_list_with_mimes.push((mime.clone(), file_path.clone()));
 
-            let content = content.expect("content never None if client is None");
+        let (content, compression) = if is_compressible(&mime) {
+            (try!(compress(&content)), Some(GZIP.to_owned()))
+        } else {
+            (content, None)
+        };
 
-            if rows.get(0).get::<usize, i64>(0) == 0 {
-                try!(trans.query("INSERT INTO files (path, mime, content) VALUES ($1, $2, $3)",
-                                &[&path, &mime, &content]));
-            } else {
-                try!(trans.query("UPDATE files SET mime = $2, content = $3, date_updated = NOW() \
-                                WHERE path = $1",
-                                &[&path, &mime, &content]));
-            }
-        }
+        blobs.push(Blob {
+            path: bucket_path,
+            mime: mime,
+            date_updated: time::get_time(),
+            content: content,
+            compression: compression,
+        });
     }
 
-    try!(trans.commit());
-
-    file_list_to_json(file_list_with_mimes)
+    Ok((blobs, file_list_with_mimes))
 }
 
 
@@ -264,12 +183,17 @@ fn file_list_to_json(file_list: Vec<(String, PathBuf)>) -> Result<Json> {
     Ok(file_list_json.to_json())
 }
 
+/// Moves `n` files from the `files` table into S3
 pub fn move_to_s3(conn: &Connection, n: usize) -> Result<usize> {
     let trans = try!(conn.transaction());
-    let client = s3_client().expect("configured s3");
+    let s3 = try!(S3Storage::from_env());
+    let client = s3.client();
 
     let rows = try!(trans.query(
-            &format!("SELECT path, mime, content FROM files WHERE content != E'in-s3' LIMIT {}", n),
+            &format!("SELECT path, mime, content, compression
+                      FROM files
+                      WHERE content != E'in-s3'
+                      LIMIT {}", n),
             &[]));
     let count = rows.len();
 
@@ -279,12 +203,14 @@ pub fn move_to_s3(conn: &Connection, n: usize) -> Result<usize> {
         let path: String = row.get(0);
         let mime: String = row.get(1);
         let content: Vec<u8> = row.get(2);
+        let compression: Option<String> = row.get(3);
         let path_1 = path.clone();
         futures.push(client.put_object(PutObjectRequest {
-            bucket: "rust-docs-rs".into(),
-            key: path.clone(),
+            bucket: s3.config().bucket.clone(),
+            key: s3.config().key(&path),
             body: Some(content.into()),
             content_type: Some(mime),
+            content_encoding: compression,
             ..Default::default()
         }).map(move |_| {
             path_1
@@ -315,7 +241,32 @@ pub fn move_to_s3(conn: &Connection, n: usize) -> Result<usize> {
 mod test {
     extern crate env_logger;
     use std::env;
-    use super::get_file_list;
+    use std::fs;
+    use std::sync::Mutex;
+    use error::Result;
+    use storage::{Blob, Storage};
+    use tempdir::TempDir;
+    use super::{add_path_into_archive, get_file_list, UPLOAD_BATCH_SIZE};
+
+    /// Storage without archive support recording the number of files of each `store` call
+    struct RecordingStorage {
+        batches: Mutex<Vec<usize>>,
+    }
+
+    impl Storage for RecordingStorage {
+        fn get(&self, _path: &str) -> Result<Option<Blob>> {
+            Ok(None)
+        }
+
+        fn store(&self, blobs: &[Blob]) -> Result<()> {
+            self.batches.lock().unwrap().push(blobs.len());
+            Ok(())
+        }
+
+        fn delete_prefix(&self, _prefix: &str) -> Result<()> {
+            Ok(())
+        }
+    }
 
     #[test]
     fn test_get_file_list() {
@@ -328,4 +279,16 @@ mod test {
         let files = get_file_list(env::current_dir().unwrap().join("Cargo.toml")).unwrap();
         assert_eq!(files[0], std::path::Path::new("Cargo.toml"));
     }
+
+    #[test]
+    fn test_add_path_into_archive_without_archive_support() {
+        let dir = TempDir::new("add-path-into-archive").unwrap();
+        for i in 0..UPLOAD_BATCH_SIZE + 1 {
+            fs::write(dir.path().join(format!("{}.html", i)), "<html></html>").unwrap();
+        }
+
+        let storage = RecordingStorage { batches: Mutex::new(Vec::new()) };
+        add_path_into_archive(&storage, "rustdoc/acme/0.1.0", dir.path()).unwrap();
+        assert_eq!(*storage.batches.lock().unwrap(), vec![UPLOAD_BATCH_SIZE, 1]);
+    }
 }
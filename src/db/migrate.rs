@@ -193,6 +193,217 @@ pub fn migrate(version: Option<Version>) -> CratesfyiResult<()> {
             // downgrade query
             "DROP TABLE sandbox_overrides;"
         ),
+        migration!(
+            // version
+            4,
+            // description
+            "Added compression column to files table",
+            // upgrade query
+            "ALTER TABLE files ADD COLUMN compression VARCHAR;",
+            // downgrade query
+            "ALTER TABLE files DROP COLUMN compression;"
+        ),
+        migration!(
+            // version
+            5,
+            // description
+            "Added archive_index table",
+            // upgrade query
+            "CREATE TABLE archive_index (
+                 path VARCHAR(4096) NOT NULL PRIMARY KEY,
+                 archive VARCHAR(4096) NOT NULL,
+                 file_offset BIGINT NOT NULL,
+                 file_length BIGINT NOT NULL,
+                 mime VARCHAR(100) NOT NULL,
+                 compression VARCHAR,
+                 date_updated TIMESTAMP NOT NULL DEFAULT NOW()
+             );
+             CREATE INDEX archive_index_archive_idx ON archive_index (archive);",
+            // downgrade query
+            "DROP TABLE archive_index;"
+        ),
+        migration!(
+            // version
+            6,
+            // description
+            "Added file_hashes and blobs tables",
+            // upgrade query
+            "CREATE TABLE file_hashes (
+                 path VARCHAR(4096) NOT NULL PRIMARY KEY,
+                 hash VARCHAR(64) NOT NULL,
+                 mime VARCHAR(100) NOT NULL,
+                 compression VARCHAR,
+                 date_updated TIMESTAMP NOT NULL DEFAULT NOW()
+             );
+             CREATE INDEX file_hashes_hash_idx ON file_hashes (hash);
+             CREATE TABLE blobs (
+                 hash VARCHAR(64) NOT NULL PRIMARY KEY
+             );",
+            // downgrade query
+            "DROP TABLE blobs;
+             DROP TABLE file_hashes;"
+        ),
+        migration!(
+            // version
+            7,
+            // description
+            "Fixed owner_rels foreign key referencing releases instead of crates",
+            // upgrade query
+            "ALTER TABLE owner_rels
+                 DROP CONSTRAINT owner_rels_cid_fkey,
+                 ADD CONSTRAINT owner_rels_cid_fkey FOREIGN KEY (cid) REFERENCES crates(id);",
+            // downgrade query
+            "ALTER TABLE owner_rels
+                 DROP CONSTRAINT owner_rels_cid_fkey,
+                 ADD CONSTRAINT owner_rels_cid_fkey FOREIGN KEY (cid) REFERENCES releases(id);"
+        ),
+        migration!(
+            // version
+            8,
+            // description
+            "Added claimed_at column to build queue",
+            // upgrade query
+            "ALTER TABLE queue ADD COLUMN claimed_at TIMESTAMP;",
+            // downgrade query
+            "ALTER TABLE queue DROP COLUMN claimed_at;"
+        ),
+        migration!(
+            // version
+            9,
+            // description
+            "Added claimed_by and heartbeat columns to build queue",
+            // upgrade query
+            "ALTER TABLE queue ADD COLUMN claimed_by VARCHAR(255),
+                               ADD COLUMN heartbeat TIMESTAMP;",
+            // downgrade query
+            "ALTER TABLE queue DROP COLUMN claimed_by,
+                               DROP COLUMN heartbeat;"
+        ),
+        migration!(
+            // version
+            10,
+            // description
+            "Added last_error and next_attempt_at columns to build queue",
+            // upgrade query
+            "ALTER TABLE queue ADD COLUMN last_error TEXT,
+                               ADD COLUMN next_attempt_at TIMESTAMP;",
+            // downgrade query
+            "ALTER TABLE queue DROP COLUMN last_error,
+                               DROP COLUMN next_attempt_at;"
+        ),
+        migration!(
+            // version
+            11,
+            // description
+            "Added rebuild campaigns",
+            // upgrade query
+            "CREATE TABLE rebuild_campaigns (
+                 id SERIAL PRIMARY KEY,
+                 description TEXT NOT NULL,
+                 total INT NOT NULL,
+                 built INT NOT NULL DEFAULT 0,
+                 created_at TIMESTAMP NOT NULL DEFAULT NOW()
+             );
+             ALTER TABLE queue ADD COLUMN campaign_id INT
+                 REFERENCES rebuild_campaigns(id) ON DELETE SET NULL;",
+            // downgrade query
+            "ALTER TABLE queue DROP COLUMN campaign_id;
+             DROP TABLE rebuild_campaigns;"
+        ),
+        migration!(
+            // version
+            12,
+            // description
+            "Added version requirement and build configuration to sandbox_overrides",
+            // upgrade query
+            "ALTER TABLE sandbox_overrides
+                 DROP CONSTRAINT sandbox_overrides_pkey,
+                 ADD COLUMN version_req VARCHAR NOT NULL DEFAULT '*',
+                 ADD COLUMN networking BOOLEAN,
+                 ADD COLUMN max_log_size INTEGER,
+                 ADD COLUMN extra_targets VARCHAR[],
+                 ADD COLUMN features VARCHAR[],
+                 ADD COLUMN toolchain VARCHAR;
+             ALTER TABLE sandbox_overrides ADD PRIMARY KEY (crate_name, version_req);",
+            // downgrade query
+            "DELETE FROM sandbox_overrides WHERE version_req != '*';
+             ALTER TABLE sandbox_overrides
+                 DROP CONSTRAINT sandbox_overrides_pkey,
+                 DROP COLUMN version_req,
+                 DROP COLUMN networking,
+                 DROP COLUMN max_log_size,
+                 DROP COLUMN extra_targets,
+                 DROP COLUMN features,
+                 DROP COLUMN toolchain;
+             ALTER TABLE sandbox_overrides ADD PRIMARY KEY (crate_name);"
+        ),
+        migration!(
+            // version
+            13,
+            // description
+            "Added identical_doc_targets column to releases",
+            // upgrade query
+            "ALTER TABLE releases ADD COLUMN identical_doc_targets JSON;",
+            // downgrade query
+            "ALTER TABLE releases DROP COLUMN identical_doc_targets;"
+        ),
+        migration!(
+            // version
+            14,
+            // description
+            "Added build_steps table",
+            // upgrade query
+            "CREATE TABLE build_steps (
+                 id SERIAL PRIMARY KEY,
+                 build_id INT NOT NULL REFERENCES builds(id) ON DELETE CASCADE,
+                 position INT NOT NULL,
+                 name VARCHAR(255) NOT NULL,
+                 duration_ms BIGINT NOT NULL,
+                 successful BOOL NOT NULL,
+                 error TEXT,
+                 output TEXT
+             );
+             CREATE INDEX ON build_steps (build_id);",
+            // downgrade query
+            "DROP TABLE build_steps;"
+        ),
+        migration!(
+            // version
+            15,
+            // description
+            "Added failure_kind column to builds",
+            // upgrade query
+            "ALTER TABLE builds ADD COLUMN failure_kind VARCHAR(32);",
+            // downgrade query
+            "ALTER TABLE builds DROP COLUMN failure_kind;"
+        ),
+        migration!(
+            // version
+            16,
+            // description
+            "Added tables recording the progress of build world runs",
+            // upgrade query
+            "CREATE TABLE build_world_runs (
+                 id SERIAL PRIMARY KEY,
+                 description TEXT NOT NULL,
+                 selection JSON NOT NULL,
+                 started_at TIMESTAMP NOT NULL DEFAULT NOW(),
+                 finished_at TIMESTAMP
+             );
+             CREATE TABLE build_world_releases (
+                 run_id INT NOT NULL REFERENCES build_world_runs(id) ON DELETE CASCADE,
+                 position INT NOT NULL,
+                 name VARCHAR(255) NOT NULL,
+                 version VARCHAR(100) NOT NULL,
+                 status VARCHAR(16),
+                 duration_ms BIGINT,
+                 error TEXT,
+                 PRIMARY KEY (run_id, position)
+             );",
+            // downgrade query
+            "DROP TABLE build_world_releases;
+             DROP TABLE build_world_runs;"
+        ),
     ];
 
     for migration in migrations {
@@ -2,16 +2,19 @@
 
 pub(crate) use self::add_package::add_package_into_database;
 pub(crate) use self::add_package::add_build_into_database;
+pub(crate) use self::add_package::add_build_steps_into_database;
+pub use self::delete_crate::{delete_crate, delete_version};
 pub use self::file::add_path_into_database;
 pub use self::migrate::migrate;
 
-use postgres::{Connection, TlsMode};
+use postgres::{Connection, GenericConnection, TlsMode};
 use postgres::error::Error;
 use std::env;
 use r2d2;
 use r2d2_postgres;
 
 mod add_package;
+mod delete_crate;
 pub mod file;
 mod migrate;
 
@@ -44,7 +47,7 @@ pub fn create_pool() -> r2d2::Pool<r2d2_postgres::PostgresConnectionManager> {
 ///   * latest release keywords (rank B-weight)
 ///   * latest release readme (rank C-weight)
 ///   * latest release root rustdoc (rank C-weight)
-pub fn update_search_index(conn: &Connection) -> Result<u64, Error> {
+pub fn update_search_index<C: GenericConnection>(conn: &C) -> Result<u64, Error> {
     conn.execute("
         WITH doc as (
             SELECT DISTINCT ON(releases.crate_id)
@@ -0,0 +1,111 @@
+//! Phases of a build, recorded with their duration and output to find out which part of a build
+//! is slow or failing.
+
+use error::Result;
+use std::time::{Duration, Instant};
+
+
+/// Maximum size of the output stored for a step. The end of the output is kept, since that's
+/// where the errors are.
+const MAX_STEP_OUTPUT_SIZE: usize = 16 * 1024;
+
+
+pub(crate) struct BuildStep {
+    pub(crate) name: String,
+    pub(crate) duration: Duration,
+    /// Error of a failed step, like the exit status of a command
+    pub(crate) error: Option<String>,
+    pub(crate) output: String,
+}
+
+impl BuildStep {
+    pub(crate) fn successful(&self) -> bool {
+        self.error.is_none()
+    }
+
+    pub(crate) fn duration_ms(&self) -> i64 {
+        self.duration.as_secs() as i64 * 1000 + self.duration.subsec_millis() as i64
+    }
+}
+
+
+#[derive(Default)]
+pub(crate) struct BuildSteps {
+    steps: Vec<BuildStep>,
+}
+
+impl BuildSteps {
+    pub(crate) fn new() -> BuildSteps {
+        BuildSteps::default()
+    }
+
+    /// Runs a step without output and records it
+    pub(crate) fn run<T, F: FnOnce() -> Result<T>>(&mut self, name: &str, f: F) -> Result<T> {
+        let start = Instant::now();
+        let res = f();
+        self.push(name, start.elapsed(), res.as_ref().err().map(|e| e.to_string()), "");
+        res
+    }
+
+    pub(crate) fn push(&mut self,
+                       name: &str,
+                       duration: Duration,
+                       error: Option<String>,
+                       output: &str) {
+        self.steps.push(BuildStep {
+            name: name.to_owned(),
+            duration,
+            error,
+            output: truncate_output(output),
+        });
+    }
+
+    pub(crate) fn steps(&self) -> &[BuildStep] {
+        &self.steps
+    }
+}
+
+
+/// Keeps the end of `output` if it's longer than `MAX_STEP_OUTPUT_SIZE`
+fn truncate_output(output: &str) -> String {
+    if output.len() <= MAX_STEP_OUTPUT_SIZE {
+        return output.to_owned();
+    }
+
+    let mut start = output.len() - MAX_STEP_OUTPUT_SIZE;
+    while !output.is_char_boundary(start) {
+        start += 1;
+    }
+    format!("[{} bytes truncated]\n{}", start, &output[start..])
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{truncate_output, BuildSteps, MAX_STEP_OUTPUT_SIZE};
+    use failure::err_msg;
+
+    #[test]
+    fn test_truncate_output() {
+        assert_eq!(truncate_output("error: oops"), "error: oops");
+
+        let output = format!("{}error: oops", "é".repeat(MAX_STEP_OUTPUT_SIZE));
+        let truncated = truncate_output(&output);
+        assert!(truncated.starts_with("["));
+        assert!(truncated.ends_with("error: oops"));
+        assert!(truncated.len() < MAX_STEP_OUTPUT_SIZE + 32);
+    }
+
+    #[test]
+    fn test_run() {
+        let mut steps = BuildSteps::new();
+        assert_eq!(steps.run("fetch", || Ok(42)).unwrap(), 42);
+        assert!(steps.run("upload", || -> ::error::Result<()> { Err(err_msg("no space left")) })
+            .is_err());
+
+        assert_eq!(steps.steps().len(), 2);
+        assert!(steps.steps()[0].successful());
+        assert_eq!(steps.steps()[1].name, "upload");
+        assert_eq!(steps.steps()[1].error, Some("no space left".to_owned()));
+    }
+}
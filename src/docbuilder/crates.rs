@@ -1,14 +1,16 @@
 
 use std::io::prelude::*;
 use std::io::BufReader;
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::PathBuf;
 use rustc_serialize::json::Json;
 use error::Result;
 use failure::err_msg;
+use semver::Version;
 
 fn crates_from_file<F>(path: &PathBuf, func: &mut F) -> Result<()>
-    where F: FnMut(&str, &str) -> ()
+    where F: FnMut(&str, Vec<String>) -> ()
 {
 
     let reader = try!(fs::File::open(path).map(|f| BufReader::new(f)));
@@ -47,10 +49,8 @@ fn crates_from_file<F>(path: &PathBuf, func: &mut F) -> Result<()>
     }
 
     if !name.is_empty() {
-        versions.reverse();
-        for version in versions {
-            func(&name[..], &version[..]);
-        }
+        sort_versions(&mut versions);
+        func(&name[..], versions);
     }
 
     Ok(())
@@ -58,8 +58,10 @@ fn crates_from_file<F>(path: &PathBuf, func: &mut F) -> Result<()>
 
 
 
+/// Calls `func` with the name and the versions of every crate in the index, sorted from the
+/// newest to the oldest version. Yanked versions are skipped.
 pub fn crates_from_path<F>(path: &PathBuf, func: &mut F) -> Result<()>
-    where F: FnMut(&str, &str) -> ()
+    where F: FnMut(&str, Vec<String>) -> ()
 {
 
     if !path.is_dir() {
@@ -83,3 +85,93 @@ pub fn crates_from_path<F>(path: &PathBuf, func: &mut F) -> Result<()>
 
     Ok(())
 }
+
+
+/// Sorts versions from the newest to the oldest, which is the reverse of the index order for
+/// the versions which aren't valid semver. They are kept last.
+fn sort_versions(versions: &mut Vec<String>) {
+    versions.reverse();
+    versions.sort_by(|a, b| Version::parse(b).ok().cmp(&Version::parse(a).ok()));
+}
+
+
+/// Returns the newest stable version, or the newest version if a crate only has prereleases.
+/// `versions` must be sorted from the newest to the oldest.
+pub fn latest_version(versions: &[String]) -> Option<&str> {
+    versions.iter()
+        .find(|v| Version::parse(v).map(|v| !v.is_prerelease()).unwrap_or(false))
+        .or_else(|| versions.first())
+        .map(|v| v.as_str())
+}
+
+
+/// Returns the versions older than `latest_version`, newest first. With `skip_oldest_versions`,
+/// only the latest version of every semver compatible series is returned, like `1.4.2` for
+/// `1.*` and `0.3.9` for `0.3.*`.
+pub fn older_versions(versions: &[String], skip_oldest_versions: bool) -> Vec<String> {
+    let latest = latest_version(versions);
+    let mut older: Vec<String> = if skip_oldest_versions {
+        let mut series: BTreeMap<(u64, u64, u64), Vec<String>> = BTreeMap::new();
+        for version in versions {
+            if let Ok(parsed) = Version::parse(version) {
+                series.entry(compatibility_series(&parsed)).or_insert_with(Vec::new)
+                    .push(version.clone());
+            }
+        }
+        series.values().filter_map(|versions| latest_version(versions)).map(|v| v.to_owned())
+            .collect()
+    } else {
+        versions.to_vec()
+    };
+
+    older.retain(|v| Some(v.as_str()) != latest);
+    sort_versions(&mut older);
+    older
+}
+
+
+/// Returns the part of the version which has to change for a release to be incompatible
+fn compatibility_series(version: &Version) -> (u64, u64, u64) {
+    if version.major > 0 {
+        (version.major, 0, 0)
+    } else if version.minor > 0 {
+        (0, version.minor, 0)
+    } else {
+        (0, 0, version.patch)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{latest_version, older_versions, sort_versions};
+
+    fn versions(versions: &[&str]) -> Vec<String> {
+        versions.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn test_sort_versions() {
+        // in index order, oldest first
+        let mut list = versions(&["0.9.0", "0.10.0", "invalid", "1.0.0-beta.1", "1.0.0"]);
+        sort_versions(&mut list);
+        assert_eq!(list, versions(&["1.0.0", "1.0.0-beta.1", "0.10.0", "0.9.0", "invalid"]));
+    }
+
+    #[test]
+    fn test_latest_version() {
+        assert_eq!(latest_version(&versions(&["2.0.0-alpha.1", "1.2.0", "1.1.0"])),
+                   Some("1.2.0"));
+        assert_eq!(latest_version(&versions(&["0.1.0-alpha.2", "0.1.0-alpha.1"])),
+                   Some("0.1.0-alpha.2"));
+        assert_eq!(latest_version(&[]), None);
+    }
+
+    #[test]
+    fn test_older_versions() {
+        let list = versions(&["1.1.0", "1.0.1", "1.0.0", "0.3.2", "0.3.1", "0.2.0", "0.0.2"]);
+        assert_eq!(older_versions(&list, false),
+                   versions(&["1.0.1", "1.0.0", "0.3.2", "0.3.1", "0.2.0", "0.0.2"]));
+        assert_eq!(older_versions(&list, true), versions(&["0.3.2", "0.2.0", "0.0.2"]));
+    }
+}
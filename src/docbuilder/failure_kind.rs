@@ -0,0 +1,159 @@
+//! Causes of failed builds, to tell crates which don't compile apart from the ones hitting the
+//! sandbox limits or a problem of the builder.
+
+use failure::Error;
+use regex::Regex;
+use rustwide::cmd::CommandError;
+use std::fmt;
+
+
+/// Messages printed when a crate needs a native library which isn't installed in the build image
+const MISSING_NATIVE_LIBRARY_PATTERNS: &[&str] = &[
+    "could not find native static library",
+    "was not found in the pkg-config search path",
+    "pkg-config has not been configured",
+    "Could not run `\"pkg-config\"",
+    "unable to find library",
+];
+
+/// Messages printed by rustc and LLVM when they can't allocate memory. Kills by the sandbox are
+/// detected from the exit status.
+const OUT_OF_MEMORY_PATTERN: &str =
+    r"memory allocation of \d+ bytes failed|LLVM ERROR: out of memory";
+
+/// Exit status of a process killed with SIGKILL, which is how the sandbox stops builds exceeding
+/// the memory limit
+const SIGKILL_EXIT_STATUS: i32 = 137;
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FailureKind {
+    /// The documentation of the crate failed to compile
+    CompileError,
+    /// The build exceeded the time limit
+    Timeout,
+    /// The build exceeded the memory limit
+    OutOfMemory,
+    /// The crate needs a native library which isn't available in the build image
+    MissingNativeLibrary,
+    /// The build couldn't be executed because of a problem of the builder
+    Infrastructure,
+}
+
+impl FailureKind {
+    pub fn all() -> &'static [FailureKind] {
+        &[FailureKind::CompileError,
+          FailureKind::Timeout,
+          FailureKind::OutOfMemory,
+          FailureKind::MissingNativeLibrary,
+          FailureKind::Infrastructure]
+    }
+
+    /// Returns the name stored in the database and used in URLs
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            FailureKind::CompileError => "compile_error",
+            FailureKind::Timeout => "timeout",
+            FailureKind::OutOfMemory => "out_of_memory",
+            FailureKind::MissingNativeLibrary => "missing_native_library",
+            FailureKind::Infrastructure => "infrastructure",
+        }
+    }
+
+    pub fn from_str(name: &str) -> Option<FailureKind> {
+        FailureKind::all().iter().cloned().find(|kind| kind.as_str() == name)
+    }
+
+    /// Returns a short description shown on the website
+    pub fn description(&self) -> &'static str {
+        match *self {
+            FailureKind::CompileError => "Compile error",
+            FailureKind::Timeout => "Timeout",
+            FailureKind::OutOfMemory => "Out of memory",
+            FailureKind::MissingNativeLibrary => "Missing native library",
+            FailureKind::Infrastructure => "Infrastructure error",
+        }
+    }
+
+    /// Classifies the error returned by the `cargo doc` command, with the help of its log
+    pub fn classify(error: &Error, build_log: &str) -> FailureKind {
+        match error.downcast_ref::<CommandError>() {
+            Some(&CommandError::Timeout(_)) | Some(&CommandError::NoOutputFor(_)) => {
+                FailureKind::Timeout
+            }
+            Some(&CommandError::ExecutionFailed(status)) => {
+                if status.code() == Some(SIGKILL_EXIT_STATUS) {
+                    FailureKind::OutOfMemory
+                } else {
+                    FailureKind::from_log(build_log)
+                }
+            }
+            // the sandbox failed to start or to be cleaned up
+            Some(_) | None => FailureKind::Infrastructure,
+        }
+    }
+
+    /// Classifies a failed command from its log. Crates which don't compile are the default.
+    fn from_log(build_log: &str) -> FailureKind {
+        lazy_static! {
+            static ref OUT_OF_MEMORY: Regex = Regex::new(OUT_OF_MEMORY_PATTERN).unwrap();
+        }
+
+        let contains_any = |patterns: &[&str]| patterns.iter().any(|p| build_log.contains(p));
+        if OUT_OF_MEMORY.is_match(build_log) {
+            FailureKind::OutOfMemory
+        } else if contains_any(MISSING_NATIVE_LIBRARY_PATTERNS) {
+            FailureKind::MissingNativeLibrary
+        } else {
+            FailureKind::CompileError
+        }
+    }
+}
+
+impl fmt::Display for FailureKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::FailureKind;
+    use failure::err_msg;
+    use rustwide::cmd::CommandError;
+
+    #[test]
+    fn test_from_str() {
+        for kind in FailureKind::all() {
+            assert_eq!(FailureKind::from_str(kind.as_str()), Some(*kind));
+        }
+        assert_eq!(FailureKind::from_str("segfault"), None);
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(FailureKind::classify(&CommandError::Timeout(900).into(), ""),
+                   FailureKind::Timeout);
+        assert_eq!(FailureKind::classify(&err_msg("failed to start the container"), ""),
+                   FailureKind::Infrastructure);
+
+        assert_eq!(FailureKind::from_log("error[E0425]: cannot find value `x` in this scope"),
+                   FailureKind::CompileError);
+        assert_eq!(FailureKind::from_log("Package openssl was not found in the pkg-config \
+                                          search path."),
+                   FailureKind::MissingNativeLibrary);
+        assert_eq!(FailureKind::from_log("memory allocation of 4096 bytes failed"),
+                   FailureKind::OutOfMemory);
+        assert_eq!(FailureKind::from_log("LLVM ERROR: out of memory"),
+                   FailureKind::OutOfMemory);
+
+        // crates talking about memory or signals in their own output
+        assert_eq!(FailureKind::from_log("test alloc::tests::out_of_memory ... ok\n\
+                                          warning: unused variable: `on_sigkill`\n\
+                                          error[E0308]: mismatched types"),
+                   FailureKind::CompileError);
+        assert_eq!(FailureKind::from_log("error: returns an out of memory error"),
+                   FailureKind::CompileError);
+    }
+}
@@ -1,3 +1,4 @@
+use docbuilder::overrides::BuildOverrides;
 use error::Result;
 use postgres::Connection;
 use std::collections::BTreeMap;
@@ -22,24 +23,25 @@ impl Default for Limits {
 }
 
 impl Limits {
-    pub(crate) fn for_crate(conn: &Connection, name: &str) -> Result<Self> {
-        let mut limits = Self::default();
+    pub(crate) fn for_crate(conn: &Connection, name: &str, version: Option<&str>) -> Result<Self> {
+        Ok(Self::from_overrides(&BuildOverrides::for_release(conn, name, version)?))
+    }
 
-        let res = conn.query(
-            "SELECT * FROM sandbox_overrides WHERE crate_name = $1;",
-            &[&name],
-        )?;
-        if !res.is_empty() {
-            let row = res.get(0);
-            if let Some(memory) = row.get::<_, Option<i32>>("max_memory_bytes") {
-                limits.memory = memory as usize;
-            }
-            if let Some(timeout) = row.get::<_, Option<i32>>("timeout_seconds") {
-                limits.timeout = Duration::from_secs(timeout as u64);
-            }
+    pub(crate) fn from_overrides(overrides: &BuildOverrides) -> Self {
+        let mut limits = Self::default();
+        if let Some(memory) = overrides.max_memory_bytes {
+            limits.memory = memory as usize;
         }
-
-        Ok(limits)
+        if let Some(timeout) = overrides.timeout_seconds {
+            limits.timeout = Duration::from_secs(timeout as u64);
+        }
+        if let Some(networking) = overrides.networking {
+            limits.networking = networking;
+        }
+        if let Some(max_log_size) = overrides.max_log_size {
+            limits.max_log_size = max_log_size as usize;
+        }
+        limits
     }
 
     pub(crate) fn memory(&self) -> usize {
@@ -1,6 +1,7 @@
 
 use std::path::Path;
 use toml::Value;
+use toml::value::Table;
 use error::Result;
 use failure::err_msg;
 
@@ -20,8 +21,10 @@ use failure::err_msg;
 /// all-features = true
 /// no-default-features = true
 /// default-target = "x86_64-unknown-linux-gnu"
+/// targets = [ "x86_64-unknown-linux-gnu", "wasm32-unknown-unknown" ]
 /// rustc-args = [ "--example-rustc-arg" ]
 /// rustdoc-args = [ "--example-rustdoc-arg" ]
+/// toolchain = "nightly-2019-10-01"
 /// dependencies = [ "example-system-dependency" ]
 /// ```
 ///
@@ -44,43 +47,89 @@ pub struct Metadata {
     /// is always built on this target. You can change default target by setting this.
     pub default_target: Option<String>,
 
+    /// List of targets docs.rs will build documentation for.
+    ///
+    /// By default, docs.rs builds documentation for a set of common targets. Set this to replace
+    /// them, for example to build only for `thumbv7em-none-eabihf` or `wasm32-unknown-unknown`.
+    /// The first target is used as default target if `default-target` is not set. Only the first
+    /// `MAX_TARGETS` targets are built.
+    pub targets: Option<Vec<String>>,
+
     /// List of command line arguments for `rustc`.
     pub rustc_args: Option<Vec<String>>,
 
     /// List of command line arguments for `rustdoc`.
     pub rustdoc_args: Option<Vec<String>>,
 
+    /// Toolchain docs.rs will build documentation with.
+    ///
+    /// Only the toolchains installed on docs.rs can be used, the default toolchain is used
+    /// otherwise.
+    pub toolchain: Option<String>,
+
     /// System dependencies.
     ///
-    /// Docs.rs is running on a Debian jessie.
+    /// Debian packages installed in the build image before building the documentation. Only the
+    /// packages allowed by docs.rs administrators are installed.
     pub dependencies: Option<Vec<String>>,
 }
 
 
 
+/// Number of targets a crate can request, every target is a full build
+pub(crate) const MAX_TARGETS: usize = 10;
+
+
+/// Keys of the `[package.metadata.docs.rs]` table
+const KNOWN_KEYS: &[&str] = &[
+    "features",
+    "all-features",
+    "no-default-features",
+    "default-target",
+    "targets",
+    "rustc-args",
+    "rustdoc-args",
+    "toolchain",
+    "dependencies",
+];
+
+
+
 impl Metadata {
     pub(crate) fn from_source_dir(source_dir: &Path) -> Result<Metadata> {
+        Metadata::from_source_dir_with_warnings(source_dir).map(|(metadata, _)| metadata)
+    }
+
+    /// Same as `from_source_dir`, but also returns the problems found in the manifest. A manifest
+    /// which can't be read or parsed is reported as a warning and the defaults are used.
+    pub(crate) fn from_source_dir_with_warnings(source_dir: &Path)
+                                                -> Result<(Metadata, Vec<String>)> {
         for c in ["Cargo.toml.orig", "Cargo.toml"].iter() {
             let manifest_path = source_dir.clone().join(c);
             if manifest_path.exists() {
-                return Ok(Metadata::from_manifest(manifest_path));
+                return Ok(match Metadata::check_manifest(manifest_path) {
+                    Ok(checked) => checked,
+                    Err(e) => (Metadata::default(), vec![format!("invalid manifest: {}", e)]),
+                });
             }
         }
         Err(err_msg("Manifest not found"))
     }
 
     pub fn from_manifest<P: AsRef<Path>>(path: P) -> Metadata {
+        Metadata::check_manifest(path).map(|(metadata, _)| metadata)
+            .unwrap_or_else(|_| Metadata::default())
+    }
+
+    /// Parses the `[package.metadata.docs.rs]` table of a manifest, and returns the problems
+    /// found in it: unknown keys, values of the wrong type and features the crate doesn't have.
+    /// Those are ignored by the builder, which uses the defaults instead.
+    pub fn check_manifest<P: AsRef<Path>>(path: P) -> Result<(Metadata, Vec<String>)> {
         use std::fs::File;
         use std::io::Read;
-        let mut f = match File::open(path) {
-            Ok(f) => f,
-            Err(_) => return Metadata::default(),
-        };
         let mut s = String::new();
-        if let Err(_) = f.read_to_string(&mut s) {
-            return Metadata::default();
-        }
-        Metadata::from_str(&s)
+        File::open(path)?.read_to_string(&mut s)?;
+        Metadata::check_str(&s)
     }
 
 
@@ -91,51 +140,163 @@ impl Metadata {
             all_features: false,
             no_default_features: false,
             default_target: None,
+            targets: None,
             rustc_args: None,
             rustdoc_args: None,
+            toolchain: None,
             dependencies: None,
         }
     }
 
 
+    #[cfg(test)]
     fn from_str(manifest: &str) -> Metadata {
+        Metadata::check_str(manifest).map(|(metadata, _)| metadata)
+            .unwrap_or_else(|_| Metadata::default())
+    }
+
+
+    fn check_str(manifest: &str) -> Result<(Metadata, Vec<String>)> {
+        let manifest = manifest.parse::<Value>()?;
         let mut metadata = Metadata::default();
+        let mut warnings = Vec::new();
 
-        let manifest = match manifest.parse::<Value>() {
-            Ok(m) => m,
-            Err(_) => return metadata,
+        let table = match manifest.get("package").and_then(|p| p.get("metadata"))
+            .and_then(|p| p.get("docs")).and_then(|p| p.get("rs")) {
+            Some(&Value::Table(ref table)) => table,
+            Some(value) => {
+                warnings.push(format!("`package.metadata.docs.rs` should be a table, found {}",
+                                      value.type_str()));
+                return Ok((metadata, warnings));
+            }
+            None => return Ok((metadata, warnings)),
         };
 
-        if let Some(table) = manifest.get("package").and_then(|p| p.as_table())
-            .and_then(|p| p.get("metadata")).and_then(|p| p.as_table())
-                .and_then(|p| p.get("docs")).and_then(|p| p.as_table())
-                .and_then(|p| p.get("rs")).and_then(|p| p.as_table()) {
-                    metadata.features = table.get("features").and_then(|f| f.as_array())
-                        .and_then(|f| f.iter().map(|v| v.as_str().map(|v| v.to_owned())).collect());
-                    metadata.no_default_features = table.get("no-default-features")
-                        .and_then(|v| v.as_bool()).unwrap_or(metadata.no_default_features);
-                    metadata.all_features = table.get("all-features")
-                        .and_then(|v| v.as_bool()).unwrap_or(metadata.all_features);
-                    metadata.default_target = table.get("default-target")
-                        .and_then(|v| v.as_str()).map(|v| v.to_owned());
-                    metadata.rustc_args = table.get("rustc-args").and_then(|f| f.as_array())
-                        .and_then(|f| f.iter().map(|v| v.as_str().map(|v| v.to_owned())).collect());
-                    metadata.rustdoc_args = table.get("rustdoc-args").and_then(|f| f.as_array())
-                        .and_then(|f| f.iter().map(|v| v.as_str().map(|v| v.to_owned())).collect());
-                    metadata.dependencies = table.get("dependencies").and_then(|f| f.as_array())
-                        .and_then(|f| f.iter().map(|v| v.as_str().map(|v| v.to_owned())).collect());
+        for key in table.keys() {
+            if KNOWN_KEYS.contains(&key.as_str()) {
+                continue;
+            }
+            let suggestion = key.replace("_", "-");
+            if KNOWN_KEYS.contains(&suggestion.as_str()) {
+                warnings.push(format!("unknown key `{}`, did you mean `{}`?", key, suggestion));
+            } else {
+                warnings.push(format!("unknown key `{}`", key));
+            }
+        }
+
+        {
+            let mut check = TableChecker { table, warnings: &mut warnings };
+            metadata.features = check.string_array("features");
+            metadata.all_features = check.boolean("all-features").unwrap_or(metadata.all_features);
+            metadata.no_default_features = check.boolean("no-default-features")
+                .unwrap_or(metadata.no_default_features);
+            metadata.default_target = check.string("default-target");
+            metadata.targets = check.string_array("targets");
+            metadata.rustc_args = check.string_array("rustc-args");
+            metadata.rustdoc_args = check.string_array("rustdoc-args");
+            metadata.toolchain = check.string("toolchain");
+            metadata.dependencies = check.string_array("dependencies");
+        }
+
+        if let Some(ref mut targets) = metadata.targets {
+            if targets.len() > MAX_TARGETS {
+                warnings.push(format!("`targets` lists {} targets, only the first {} are built",
+                                      targets.len(),
+                                      MAX_TARGETS));
+                targets.truncate(MAX_TARGETS);
+            }
+        }
+
+        if let Some(ref features) = metadata.features {
+            let declared = declared_features(&manifest);
+            for feature in features {
+                // features of dependencies can't be checked without their manifest
+                if !feature.contains('/') && !declared.contains(&feature.as_str()) {
+                    warnings.push(format!("feature `{}` is not declared in `[features]`",
+                                          feature));
                 }
+            }
+        }
 
-        metadata
+        Ok((metadata, warnings))
     }
 }
 
 
+/// Reads the values of the `[package.metadata.docs.rs]` table, recording a warning for each value
+/// of the wrong type
+struct TableChecker<'a> {
+    table: &'a Table,
+    warnings: &'a mut Vec<String>,
+}
+
+impl<'a> TableChecker<'a> {
+    fn boolean(&mut self, key: &str) -> Option<bool> {
+        match self.table.get(key) {
+            Some(&Value::Boolean(value)) => Some(value),
+            Some(value) => self.wrong_type(key, "a boolean", value),
+            None => None,
+        }
+    }
+
+    fn string(&mut self, key: &str) -> Option<String> {
+        match self.table.get(key) {
+            Some(&Value::String(ref value)) => Some(value.to_owned()),
+            Some(value) => self.wrong_type(key, "a string", value),
+            None => None,
+        }
+    }
+
+    fn string_array(&mut self, key: &str) -> Option<Vec<String>> {
+        let value = match self.table.get(key) {
+            Some(value) => value,
+            None => return None,
+        };
+        let strings = value.as_array()
+            .and_then(|values| values.iter().map(|v| v.as_str().map(|v| v.to_owned())).collect());
+        match strings {
+            Some(strings) => Some(strings),
+            None => self.wrong_type(key, "an array of strings", value),
+        }
+    }
+
+    fn wrong_type<T>(&mut self, key: &str, expected: &str, found: &Value) -> Option<T> {
+        self.warnings.push(format!("`{}` should be {}, found {}", key, expected, found.type_str()));
+        None
+    }
+}
+
+
+/// Returns the features a crate can be built with: the ones of the `[features]` table and the
+/// optional dependencies, including the build and platform specific ones
+fn declared_features(manifest: &Value) -> Vec<&str> {
+    let mut features: Vec<&str> = manifest.get("features").and_then(|f| f.as_table())
+        .map(|f| f.keys().map(|k| k.as_str()).collect())
+        .unwrap_or_default();
+
+    let platforms = manifest.get("target").and_then(|t| t.as_table())
+        .into_iter()
+        .flat_map(|targets| targets.values());
+    for tables in Some(manifest).into_iter().chain(platforms) {
+        for key in &["dependencies", "build-dependencies", "build_dependencies"] {
+            if let Some(dependencies) = tables.get(key).and_then(|d| d.as_table()) {
+                for (name, dependency) in dependencies {
+                    if dependency.get("optional").and_then(|o| o.as_bool()) == Some(true) {
+                        features.push(name);
+                    }
+                }
+            }
+        }
+    }
+    features
+}
+
+
 
 #[cfg(test)]
 mod test {
     extern crate env_logger;
-    use super::Metadata;
+    use super::{Metadata, MAX_TARGETS};
 
     #[test]
     fn test_cratesfyi_metadata() {
@@ -149,8 +310,10 @@ mod test {
             all-features = true
             no-default-features = true
             default-target = "x86_64-unknown-linux-gnu"
+            targets = [ "x86_64-unknown-linux-gnu", "wasm32-unknown-unknown" ]
             rustc-args = [ "--example-rustc-arg" ]
             rustdoc-args = [ "--example-rustdoc-arg" ]
+            toolchain = "nightly-2019-10-01"
             dependencies = [ "example-system-dependency" ]
         "#;
 
@@ -169,6 +332,11 @@ mod test {
 
         assert_eq!(metadata.default_target.unwrap(), "x86_64-unknown-linux-gnu".to_owned());
 
+        let targets = metadata.targets.unwrap();
+        assert_eq!(targets.len(), 2);
+        assert_eq!(targets[0], "x86_64-unknown-linux-gnu".to_owned());
+        assert_eq!(targets[1], "wasm32-unknown-unknown".to_owned());
+
         let rustc_args = metadata.rustc_args.unwrap();
         assert_eq!(rustc_args.len(), 1);
         assert_eq!(rustc_args[0], "--example-rustc-arg".to_owned());
@@ -177,8 +345,90 @@ mod test {
         assert_eq!(rustdoc_args.len(), 1);
         assert_eq!(rustdoc_args[0], "--example-rustdoc-arg".to_owned());
 
+        assert_eq!(metadata.toolchain.unwrap(), "nightly-2019-10-01".to_owned());
+
         let dependencies = metadata.dependencies.unwrap();
         assert_eq!(dependencies.len(), 1);
         assert_eq!(dependencies[0], "example-system-dependency".to_owned());
     }
+
+    #[test]
+    fn test_check_metadata() {
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [features]
+            feature1 = []
+
+            [dependencies]
+            serde = { version = "1", optional = true }
+
+            [package.metadata.docs.rs]
+            features = [ "feature1", "serde", "feature2", "log/std" ]
+            all_features = true
+            no-default-features = "yes"
+            targets = "x86_64-unknown-linux-gnu"
+        "#;
+
+        let (metadata, warnings) = Metadata::check_str(manifest).unwrap();
+        assert_eq!(warnings, vec![
+            "unknown key `all_features`, did you mean `all-features`?".to_owned(),
+            "`no-default-features` should be a boolean, found string".to_owned(),
+            "`targets` should be an array of strings, found string".to_owned(),
+            "feature `feature2` is not declared in `[features]`".to_owned(),
+        ]);
+        assert!(!metadata.all_features);
+        assert!(!metadata.no_default_features);
+        assert!(metadata.targets.is_none());
+        assert_eq!(metadata.features.unwrap().len(), 4);
+
+        assert!(Metadata::check_str("[package").is_err());
+    }
+
+    #[test]
+    fn test_check_metadata_optional_dependencies() {
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [build-dependencies]
+            cc = { version = "1", optional = true }
+
+            [target.'cfg(windows)'.dependencies]
+            winapi = { version = "0.3", optional = true }
+
+            [target.'cfg(unix)'.build-dependencies]
+            pkg-config = { version = "0.3", optional = true }
+
+            [target.'cfg(unix)'.dependencies]
+            libc = "0.2"
+
+            [package.metadata.docs.rs]
+            features = [ "cc", "winapi", "pkg-config", "libc" ]
+        "#;
+
+        let (_, warnings) = Metadata::check_str(manifest).unwrap();
+        assert_eq!(warnings, vec!["feature `libc` is not declared in `[features]`".to_owned()]);
+    }
+
+    #[test]
+    fn test_too_many_targets() {
+        let targets: Vec<String> = (0..MAX_TARGETS + 2)
+            .map(|i| format!("\"target-{}\"", i))
+            .collect();
+        let manifest = format!("[package]\nname = \"test\"\n\n\
+                                [package.metadata.docs.rs]\ntargets = [{}]\n",
+                               targets.join(", "));
+
+        let (metadata, warnings) = Metadata::check_str(&manifest).unwrap();
+        assert_eq!(warnings, vec![
+            format!("`targets` lists {} targets, only the first {} are built",
+                    MAX_TARGETS + 2,
+                    MAX_TARGETS),
+        ]);
+        let targets = metadata.targets.unwrap();
+        assert_eq!(targets.len(), MAX_TARGETS);
+        assert_eq!(targets[0], "target-0");
+    }
 }
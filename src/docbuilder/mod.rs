@@ -1,14 +1,25 @@
 
 pub mod options;
 pub mod metadata;
+pub mod overrides;
+pub mod world;
+mod build_steps;
+mod failure_kind;
 mod limits;
+mod offline_docs;
 mod rustwide_builder;
 mod crates;
 mod queue;
+mod system_deps;
+mod toolchains;
 
-pub use self::rustwide_builder::RustwideBuilder;
+pub use self::rustwide_builder::{LocalBuild, RustwideBuilder};
 pub(crate) use self::rustwide_builder::BuildResult;
+pub(crate) use self::build_steps::BuildSteps;
+pub(crate) use self::failure_kind::FailureKind;
 pub(crate) use self::limits::Limits;
+pub(crate) use self::offline_docs::{offline_docs_file_name, offline_docs_path};
+pub(crate) use self::queue::HEARTBEAT_TIMEOUT;
 
 
 use std::fs;
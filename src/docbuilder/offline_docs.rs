@@ -0,0 +1,113 @@
+//! Downloadable archives of the documentation of a release, to browse it without network access.
+//!
+//! The archive is built once the documentation is uploaded, from the same directory. Rustdoc
+//! output on docs.rs loads its static files from the root of the site, so these links are made
+//! relative and the static files of the build are added to the archive.
+
+use db::file::get_file_list;
+use error::Result;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use regex::{Captures, Regex};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tar;
+use time;
+
+
+pub(crate) const OFFLINE_DOCS_MIME: &'static str = "application/gzip";
+
+
+/// Returns the storage path of the offline documentation of a release
+pub(crate) fn offline_docs_path(name: &str, version: &str) -> String {
+    format!("downloads/{}/{}/{}", name, version, offline_docs_file_name(name, version))
+}
+
+
+pub(crate) fn offline_docs_file_name(name: &str, version: &str) -> String {
+    format!("{}-{}-docs.tar.gz", name, version)
+}
+
+
+/// Packs the documentation in `doc_dir` into a gzipped tarball, with every file under a
+/// `{name}-{version}` directory. The files at the top of `static_dir` which aren't in `doc_dir`
+/// are added next to the documentation.
+pub(crate) fn build_offline_docs(doc_dir: &Path,
+                                 static_dir: &Path,
+                                 name: &str,
+                                 version: &str)
+                                 -> Result<Vec<u8>> {
+    let root = PathBuf::from(format!("{}-{}", name, version));
+    let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+
+    for file in get_file_list(doc_dir)? {
+        let content = fs::read(doc_dir.join(&file))?;
+        if file.extension().map_or(false, |ext| ext == "html") {
+            let depth = file.components().count() - 1;
+            let html = rewrite_static_links(&String::from_utf8_lossy(&content), depth);
+            append_file(&mut builder, &root.join(&file), html.as_bytes())?;
+        } else {
+            append_file(&mut builder, &root.join(&file), &content)?;
+        }
+    }
+
+    for entry in fs::read_dir(static_dir)? {
+        let entry = entry?;
+        // skips the lock file of rustdoc
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if entry.file_type()?.is_file() && !hidden && !doc_dir.join(entry.file_name()).exists() {
+            append_file(&mut builder, &root.join(entry.file_name()), &fs::read(entry.path())?)?;
+        }
+    }
+
+    Ok(builder.into_inner()?.finish()?)
+}
+
+
+fn append_file(builder: &mut tar::Builder<GzEncoder<Vec<u8>>>,
+               path: &Path,
+               content: &[u8])
+               -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(content.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(time::get_time().sec as u64);
+    builder.append_data(&mut header, path, content)?;
+    Ok(())
+}
+
+
+/// Makes the links to the root of the site relative to a page `depth` directories deep
+fn rewrite_static_links(html: &str, depth: usize) -> String {
+    lazy_static! {
+        static ref ROOT_LINK: Regex = Regex::new(r#"(href|src)="/([^/"])"#).unwrap();
+    }
+
+    let prefix = if depth == 0 { "./".to_owned() } else { "../".repeat(depth) };
+    ROOT_LINK
+        .replace_all(html, |caps: &Captures| format!("{}=\"{}{}", &caps[1], prefix, &caps[2]))
+        .into_owned()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{offline_docs_path, rewrite_static_links};
+
+    #[test]
+    fn test_offline_docs_path() {
+        assert_eq!(offline_docs_path("rand", "0.7.2"),
+                   "downloads/rand/0.7.2/rand-0.7.2-docs.tar.gz");
+    }
+
+    #[test]
+    fn test_rewrite_static_links() {
+        let html = r#"<link rel="stylesheet" href="/rustdoc-20191001.css">
+                      <a href="//example.com/"></a><a href="../rand/index.html"></a>"#;
+        assert_eq!(rewrite_static_links(html, 2),
+                   r#"<link rel="stylesheet" href="../../rustdoc-20191001.css">
+                      <a href="//example.com/"></a><a href="../rand/index.html"></a>"#);
+        assert_eq!(rewrite_static_links(r#"<script src="/main.js">"#, 0),
+                   r#"<script src="./main.js">"#);
+    }
+}
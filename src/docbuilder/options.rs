@@ -12,8 +12,11 @@ pub struct DocBuilderOptions {
     pub crates_io_index_path: PathBuf,
     pub skip_if_exists: bool,
     pub skip_if_log_exists: bool,
+    /// Only builds the latest version of every semver compatible series of a crate
     pub skip_oldest_versions: bool,
+    /// Only builds the latest version of every crate
     pub build_only_latest_version: bool,
+    /// Stops building at the first failure
     pub debug: bool,
 }
 
@@ -49,12 +52,15 @@ impl fmt::Debug for DocBuilderOptions {
                "DocBuilderOptions {{ destination: {:?}, \
                 crates_io_index_path: {:?}, \
                 keep_build_directory: {:?}, skip_if_exists: {:?}, \
-                skip_if_log_exists: {:?}, debug: {:?} }}",
+                skip_if_log_exists: {:?}, skip_oldest_versions: {:?}, \
+                build_only_latest_version: {:?}, debug: {:?} }}",
                self.destination,
                self.crates_io_index_path,
                self.keep_build_directory,
                self.skip_if_exists,
                self.skip_if_log_exists,
+               self.skip_oldest_versions,
+               self.build_only_latest_version,
                self.debug)
     }
 }
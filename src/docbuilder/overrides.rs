@@ -0,0 +1,243 @@
+//! Build configuration overrides, set by docs.rs administrators for crates which need more
+//! resources or a special setup to build.
+//!
+//! Overrides are stored in the `sandbox_overrides` table, and apply to every release of a crate
+//! or only to the releases matching a semver requirement. When several overrides match a
+//! release, the ones scoped to a version requirement take precedence over the crate-wide one.
+
+use error::Result;
+use postgres::Connection;
+use postgres::rows::Row;
+use rustc_serialize::json::{Json, ToJson};
+use semver::{Version, VersionReq};
+use std::collections::BTreeMap;
+
+
+/// Version requirement of the overrides applying to every release of a crate
+pub const ALL_VERSIONS: &'static str = "*";
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildOverrides {
+    pub crate_name: String,
+    /// Semver requirement of the releases these overrides apply to
+    pub version_req: String,
+    pub max_memory_bytes: Option<i32>,
+    pub timeout_seconds: Option<i32>,
+    pub networking: Option<bool>,
+    pub max_log_size: Option<i32>,
+    /// Targets built in addition to the default ones
+    pub extra_targets: Option<Vec<String>>,
+    /// Features enabled in addition to the ones requested by the crate
+    pub features: Option<Vec<String>>,
+    /// Toolchain used instead of the builder's one
+    pub toolchain: Option<String>,
+}
+
+impl BuildOverrides {
+    /// Returns empty overrides for the releases of `crate_name` matching `version_req`
+    pub fn new(crate_name: &str, version_req: &str) -> BuildOverrides {
+        BuildOverrides {
+            crate_name: crate_name.to_owned(),
+            version_req: version_req.to_owned(),
+            max_memory_bytes: None,
+            timeout_seconds: None,
+            networking: None,
+            max_log_size: None,
+            extra_targets: None,
+            features: None,
+            toolchain: None,
+        }
+    }
+
+    /// Returns the overrides of a crate, merged from the ones matching `version`. Only the
+    /// crate-wide overrides are used if `version` isn't set.
+    pub fn for_release(conn: &Connection,
+                       name: &str,
+                       version: Option<&str>)
+                       -> Result<BuildOverrides> {
+        let version = version.and_then(|v| Version::parse(v).ok());
+        let mut overrides = BuildOverrides::new(name, ALL_VERSIONS);
+
+        // the crate-wide overrides sort first, so the scoped ones are merged over them
+        for row in &conn.query("SELECT * FROM sandbox_overrides
+                                WHERE crate_name = $1
+                                ORDER BY version_req != $2, version_req",
+                               &[&name, &ALL_VERSIONS])? {
+            let row = BuildOverrides::from_row(&row);
+            if row.matches(version.as_ref()) {
+                overrides.merge(&row);
+            }
+        }
+
+        Ok(overrides)
+    }
+
+    /// Returns every override, or the ones of `name` if it's set
+    pub fn list(conn: &Connection, name: Option<&str>) -> Result<Vec<BuildOverrides>> {
+        Ok(conn.query("SELECT * FROM sandbox_overrides
+                       WHERE $1::VARCHAR IS NULL OR crate_name = $1
+                       ORDER BY crate_name, version_req",
+                      &[&name])?
+            .iter()
+            .map(|row| BuildOverrides::from_row(&row))
+            .collect())
+    }
+
+    /// Returns the overrides stored for exactly this crate and version requirement
+    pub fn get(conn: &Connection,
+               name: &str,
+               version_req: &str)
+               -> Result<Option<BuildOverrides>> {
+        let rows = conn.query("SELECT * FROM sandbox_overrides
+                               WHERE crate_name = $1 AND version_req = $2",
+                              &[&name, &version_req])?;
+        Ok(rows.iter().next().map(|row| BuildOverrides::from_row(&row)))
+    }
+
+    /// Stores the overrides, replacing the ones with the same crate and version requirement
+    pub fn save(&self, conn: &Connection) -> Result<()> {
+        if self.version_req != ALL_VERSIONS {
+            if let Err(e) = VersionReq::parse(&self.version_req) {
+                bail!("invalid version requirement {}: {:?}", self.version_req, e);
+            }
+        }
+
+        conn.execute("INSERT INTO sandbox_overrides (crate_name, version_req, max_memory_bytes,
+                                                     timeout_seconds, networking, max_log_size,
+                                                     extra_targets, features, toolchain)
+                      VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                      ON CONFLICT (crate_name, version_req) DO UPDATE
+                          SET max_memory_bytes = EXCLUDED.max_memory_bytes,
+                              timeout_seconds = EXCLUDED.timeout_seconds,
+                              networking = EXCLUDED.networking,
+                              max_log_size = EXCLUDED.max_log_size,
+                              extra_targets = EXCLUDED.extra_targets,
+                              features = EXCLUDED.features,
+                              toolchain = EXCLUDED.toolchain",
+                     &[&self.crate_name,
+                       &self.version_req,
+                       &self.max_memory_bytes,
+                       &self.timeout_seconds,
+                       &self.networking,
+                       &self.max_log_size,
+                       &self.extra_targets,
+                       &self.features,
+                       &self.toolchain])?;
+        Ok(())
+    }
+
+    /// Removes the overrides of a crate, or only the ones of a version requirement. Returns the
+    /// number of removed overrides.
+    pub fn remove(conn: &Connection, name: &str, version_req: Option<&str>) -> Result<u64> {
+        Ok(conn.execute("DELETE FROM sandbox_overrides
+                         WHERE crate_name = $1 AND ($2::VARCHAR IS NULL OR version_req = $2)",
+                        &[&name, &version_req])?)
+    }
+
+    fn from_row(row: &Row) -> BuildOverrides {
+        BuildOverrides {
+            crate_name: row.get("crate_name"),
+            version_req: row.get("version_req"),
+            max_memory_bytes: row.get("max_memory_bytes"),
+            timeout_seconds: row.get("timeout_seconds"),
+            networking: row.get("networking"),
+            max_log_size: row.get("max_log_size"),
+            extra_targets: row.get("extra_targets"),
+            features: row.get("features"),
+            toolchain: row.get("toolchain"),
+        }
+    }
+
+    /// Returns whether these overrides apply to `version`. Overrides scoped to a version
+    /// requirement never apply to an unknown version.
+    fn matches(&self, version: Option<&Version>) -> bool {
+        if self.version_req == ALL_VERSIONS {
+            return true;
+        }
+        match (VersionReq::parse(&self.version_req), version) {
+            (Ok(req), Some(version)) => req.matches(version),
+            (Err(e), _) => {
+                warn!("invalid version requirement {} in overrides of {}: {:?}",
+                      self.version_req,
+                      self.crate_name,
+                      e);
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Overwrites the fields set in `other`
+    fn merge(&mut self, other: &BuildOverrides) {
+        macro_rules! merge_fields {
+            ($($field:ident),*) => {
+                $(if other.$field.is_some() {
+                    self.$field = other.$field.clone();
+                })*
+            }
+        }
+        merge_fields!(max_memory_bytes,
+                      timeout_seconds,
+                      networking,
+                      max_log_size,
+                      extra_targets,
+                      features,
+                      toolchain);
+    }
+}
+
+impl ToJson for BuildOverrides {
+    fn to_json(&self) -> Json {
+        let mut m: BTreeMap<String, Json> = BTreeMap::new();
+        m.insert("crate_name".to_string(), self.crate_name.to_json());
+        m.insert("version_req".to_string(), self.version_req.to_json());
+        m.insert("max_memory_bytes".to_string(), self.max_memory_bytes.to_json());
+        m.insert("timeout_seconds".to_string(), self.timeout_seconds.to_json());
+        m.insert("networking".to_string(), self.networking.to_json());
+        m.insert("max_log_size".to_string(), self.max_log_size.to_json());
+        m.insert("extra_targets".to_string(), self.extra_targets.to_json());
+        m.insert("features".to_string(), self.features.to_json());
+        m.insert("toolchain".to_string(), self.toolchain.to_json());
+        m.to_json()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{BuildOverrides, ALL_VERSIONS};
+    use semver::Version;
+
+    #[test]
+    fn test_matches() {
+        let all = BuildOverrides::new("rand", ALL_VERSIONS);
+        assert!(all.matches(None));
+        assert!(all.matches(Some(&Version::parse("0.7.0").unwrap())));
+
+        let scoped = BuildOverrides::new("rand", "<0.7");
+        assert!(!scoped.matches(None));
+        assert!(scoped.matches(Some(&Version::parse("0.6.5").unwrap())));
+        assert!(!scoped.matches(Some(&Version::parse("0.7.0").unwrap())));
+
+        let invalid = BuildOverrides::new("rand", "not a requirement");
+        assert!(!invalid.matches(Some(&Version::parse("0.7.0").unwrap())));
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut overrides = BuildOverrides::new("rand", ALL_VERSIONS);
+        overrides.max_memory_bytes = Some(1024);
+        overrides.networking = Some(false);
+
+        let mut scoped = BuildOverrides::new("rand", "<0.7");
+        scoped.networking = Some(true);
+        scoped.toolchain = Some("nightly-2019-10-01".to_owned());
+        overrides.merge(&scoped);
+
+        assert_eq!(overrides.max_memory_bytes, Some(1024));
+        assert_eq!(overrides.networking, Some(true));
+        assert_eq!(overrides.toolchain, Some("nightly-2019-10-01".to_owned()));
+        assert_eq!(overrides.timeout_seconds, None);
+    }
+}
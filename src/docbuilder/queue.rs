@@ -3,12 +3,34 @@
 use super::{DocBuilder, RustwideBuilder};
 use db::connect_db;
 use error::Result;
+use postgres::Connection;
 use crates_index_diff::{ChangeKind, Index};
-use utils::add_crate_to_queue;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use utils::{add_crate_to_queue, MAX_ATTEMPTS};
+
+
+/// Interval between two heartbeats of a worker building a crate from the queue
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Time without heartbeat after which a claimed queue row is considered abandoned by its
+/// worker, and can be claimed again
+pub(crate) const HEARTBEAT_TIMEOUT: &'static str = "5 minutes";
+
+/// Delay before retrying a crate after its first failed build, doubled after every failure
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Upper bound of the delay between two attempts to build a crate
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Number of retry delays passed to the database, the later attempts use the last one
+const RETRY_DELAYS: i32 = 17;
 
 
 impl DocBuilder {
     /// Updates crates.io-index repository and adds new crates into build queue.
+    /// Yanked and unyanked releases are updated in the database.
     /// Returns size of queue
     pub fn get_new_crates(&mut self) -> Result<usize> {
         let conn = try!(connect_db());
@@ -19,7 +41,28 @@ impl DocBuilder {
         // I belive this will fix ordering of queue if we get more than one crate from changes
         changes.reverse();
 
-        for krate in changes.iter().filter(|k| k.kind != ChangeKind::Yanked) {
+        // The index already points past these changes, so a failure only skips its own change
+        for krate in &changes {
+            if krate.kind == ChangeKind::Yanked {
+                match set_yanked(&conn, &krate.name, &krate.version, true) {
+                    Ok(_) => debug!("{}-{} yanked", krate.name, krate.version),
+                    Err(e) => error!("failed to yank {}-{}: {}", krate.name, krate.version, e),
+                }
+                continue;
+            }
+
+            match set_yanked(&conn, &krate.name, &krate.version, false) {
+                // the index reports an unyanked release as a new one
+                Ok(updated) if updated > 0 => {
+                    debug!("{}-{} unyanked", krate.name, krate.version);
+                    continue;
+                }
+                Ok(_) => {}
+                // queued anyway, it could be a new release
+                Err(e) => {
+                    error!("failed to unyank {}-{}: {}", krate.name, krate.version, e);
+                }
+            }
             add_crate_to_queue(&conn, &krate.name, &krate.version, 0).ok();
             debug!("{}-{} added into build queue", krate.name, krate.version);
             add_count += 1;
@@ -28,27 +71,43 @@ impl DocBuilder {
         Ok(add_count)
     }
 
+    /// Returns the number of crates in the queue which are not being built
     pub fn get_queue_count(&self) -> Result<i64> {
         let conn = try!(connect_db());
-        Ok(conn.query("SELECT COUNT(*) FROM queue WHERE attempt < 5", &[])
+        Ok(conn.query(&format!("SELECT COUNT(*) FROM queue WHERE {}", claimable_condition()),
+                      &[])
             .unwrap()
             .get(0)
             .get(0))
     }
 
-    /// Builds the top package from the queue. Returns whether the queue was empty.
+    /// Builds the top package from the queue. Returns whether a package was built.
+    ///
+    /// The package is claimed first, so several workers can build packages from the queue at
+    /// the same time. The claim is kept alive with a heartbeat while building, and is taken over
+    /// by other workers if the heartbeat stops.
     pub(crate) fn build_next_queue_package(
         &mut self,
         builder: &mut RustwideBuilder,
     ) -> Result<bool> {
         let conn = try!(connect_db());
 
-        let query = try!(conn.query("SELECT id, name, version
-                                     FROM queue
-                                     WHERE attempt < 5
-                                     ORDER BY priority ASC, attempt ASC, id ASC
-                                     LIMIT 1",
-                                    &[]));
+        // SKIP LOCKED lets concurrent workers claim the next rows instead of waiting for this one
+        let query = try!(conn.query(&format!("UPDATE queue
+                                              SET claimed_at = NOW(),
+                                                  claimed_by = $1,
+                                                  heartbeat = NOW()
+                                              WHERE id = (
+                                                  SELECT id
+                                                  FROM queue
+                                                  WHERE {}
+                                                  ORDER BY priority ASC, attempt ASC, id ASC
+                                                  LIMIT 1
+                                                  FOR UPDATE SKIP LOCKED
+                                              )
+                                              RETURNING id, name, version",
+                                             claimable_condition()),
+                                    &[&builder.worker_name()]));
 
         if query.is_empty() {
             // nothing in the queue; bail
@@ -59,17 +118,55 @@ impl DocBuilder {
         let name: String = query.get(0).get(1);
         let version: String = query.get(0).get(2);
 
-        match builder.build_package(self, &name, &version) {
+        let (stop_heartbeat, heartbeat) = match start_heartbeat(id) {
+            Ok(heartbeat) => heartbeat,
+            Err(e) => {
+                // let the other workers build it instead of waiting for the claim to time out
+                release_claim(&conn, id);
+                return Err(e);
+            }
+        };
+        let res = builder.build_package(self, &name, &version);
+        let _ = stop_heartbeat.send(());
+        let _ = heartbeat.join();
+
+        match res {
             Ok(_) => {
-                let _ = conn.execute("DELETE FROM queue WHERE id = $1", &[&id]);
+                // the progress of rebuild campaigns counts the releases built for them
+                let _ = conn.execute("WITH done AS (
+                                          DELETE FROM queue WHERE id = $1 RETURNING campaign_id
+                                      )
+                                      UPDATE rebuild_campaigns
+                                      SET built = built + 1
+                                      FROM done
+                                      WHERE rebuild_campaigns.id = done.campaign_id",
+                                     &[&id]);
             }
             Err(e) => {
-                // Increase attempt count
-                let _ = conn.execute("UPDATE queue SET attempt = attempt + 1 WHERE id = $1",
-                                     &[&id]);
-                error!("Failed to build package {}-{} from queue: {}",
+                // Increase attempt count, and wait before trying again in case the failure
+                // is transient. The count is incremented in place, since a worker reclaiming a
+                // stale row can fail at the same time.
+                let delays: Vec<i64> = (1..RETRY_DELAYS + 1)
+                    .map(|attempt| retry_delay(attempt).as_secs() as i64)
+                    .collect();
+                let attempt = conn.query("UPDATE queue
+                                          SET attempt = COALESCE(attempt, 0) + 1,
+                                              last_error = $2,
+                                              next_attempt_at = NOW() + INTERVAL '1 second' *
+                                                  ($3::BIGINT[])[LEAST(COALESCE(attempt, 0) + 1,
+                                                                       $4)],
+                                              claimed_at = NULL,
+                                              claimed_by = NULL,
+                                              heartbeat = NULL
+                                          WHERE id = $1
+                                          RETURNING attempt",
+                                         &[&id, &e.to_string(), &delays, &RETRY_DELAYS])
+                    .ok()
+                    .and_then(|rows| rows.iter().next().map(|row| row.get::<_, i32>(0)));
+                error!("Failed to build package {}-{} from queue (attempt {}): {}",
                        name,
                        version,
+                       attempt.map(|a| a.to_string()).unwrap_or_else(|| "?".to_owned()),
                        e)
             }
         }
@@ -78,11 +175,82 @@ impl DocBuilder {
     }
 }
 
+/// SQL condition matching the queue rows which can be built: rows which haven't failed too many
+/// times, whose retry delay is over, and which aren't claimed by a live worker
+fn claimable_condition() -> String {
+    format!("attempt < {} AND
+             (next_attempt_at IS NULL OR next_attempt_at <= NOW()) AND
+             (claimed_at IS NULL OR
+              COALESCE(heartbeat, claimed_at) < NOW() - INTERVAL '{}')",
+            MAX_ATTEMPTS,
+            HEARTBEAT_TIMEOUT)
+}
+
+/// Returns the delay before building a crate again after its `attempt`th failed build
+fn retry_delay(attempt: i32) -> Duration {
+    let exponent = ::std::cmp::min(attempt.max(1) - 1, 16) as u32;
+    ::std::cmp::min(RETRY_BASE_DELAY * 2u32.pow(exponent), RETRY_MAX_DELAY)
+}
+
+/// Releases the claim of a worker on a queue row it won't build
+fn release_claim(conn: &Connection, id: i32) {
+    if let Err(e) = conn.execute("UPDATE queue
+                                  SET claimed_at = NULL, claimed_by = NULL, heartbeat = NULL
+                                  WHERE id = $1",
+                                 &[&id]) {
+        error!("Failed to release the claim of queue row {}: {}", id, e);
+    }
+}
+
+/// Starts a thread updating the heartbeat of a queue row until something is sent to the
+/// returned channel, or until it's dropped.
+fn start_heartbeat(id: i32) -> Result<(Sender<()>, JoinHandle<()>)> {
+    let (sender, receiver) = mpsc::channel();
+    let handle = thread::Builder::new().name(format!("queue heartbeat {}", id)).spawn(move || {
+        let conn = match connect_db() {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Failed to connect to the database for heartbeats: {}", e);
+                return;
+            }
+        };
+        while let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(HEARTBEAT_INTERVAL) {
+            if let Err(e) = conn.execute("UPDATE queue SET heartbeat = NOW() WHERE id = $1",
+                                         &[&id]) {
+                warn!("Failed to update heartbeat of queue row {}: {}", id, e);
+            }
+        }
+    })?;
+    Ok((sender, handle))
+}
+
+/// Updates the yanked status of a release. Returns the number of updated releases, which is 0
+/// if the release was never built.
+fn set_yanked(conn: &Connection, name: &str, version: &str, yanked: bool) -> Result<u64> {
+    Ok(conn.execute("UPDATE releases
+                     SET yanked = $3
+                     FROM crates
+                     WHERE releases.crate_id = crates.id AND
+                           crates.name = $1 AND
+                           releases.version = $2",
+                    &[&name, &version, &yanked])?)
+}
+
 #[cfg(test)]
 mod test {
     extern crate env_logger;
     use std::path::PathBuf;
+    use std::time::Duration;
     use {DocBuilder, DocBuilderOptions};
+    use super::retry_delay;
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(1), Duration::from_secs(5 * 60));
+        assert_eq!(retry_delay(2), Duration::from_secs(10 * 60));
+        assert_eq!(retry_delay(4), Duration::from_secs(40 * 60));
+        assert_eq!(retry_delay(100), Duration::from_secs(24 * 60 * 60));
+    }
 
     #[test]
     #[ignore]
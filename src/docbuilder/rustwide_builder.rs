@@ -1,22 +1,42 @@
 use super::DocBuilder;
-use db::file::add_path_into_database;
-use db::{add_build_into_database, add_package_into_database, connect_db};
-use docbuilder::{crates::crates_from_path, Limits};
+use db::file::{add_path_into_archive, add_path_into_database, get_file_list};
+use db::{add_build_into_database, add_build_steps_into_database, add_package_into_database,
+         connect_db};
+use docbuilder::build_steps::BuildSteps;
+use docbuilder::failure_kind::FailureKind;
+use docbuilder::offline_docs::{build_offline_docs, offline_docs_path, OFFLINE_DOCS_MIME};
+use docbuilder::overrides::{BuildOverrides, ALL_VERSIONS};
+use docbuilder::system_deps::{self, build_image, get_allowed_dependencies,
+                              set_allowed_dependencies, SystemDependencies};
+use docbuilder::toolchains::{get_default_toolchain, get_extra_toolchains, set_default_toolchain,
+                             set_extra_toolchains};
+use docbuilder::world::{finish_world_run, next_world_release, record_world_release,
+                         start_world_run, unfinished_world_run, WorldFilter, WorldStatus};
+use docbuilder::Limits;
 use error::Result;
 use failure::ResultExt;
 use log::LevelFilter;
-use postgres::Connection;
 use rustc_serialize::json::ToJson;
-use rustwide::cmd::{Command, SandboxBuilder};
+use rustwide::cmd::{Command, SandboxBuilder, SandboxImage};
 use rustwide::logging::{self, LogStorage};
 use rustwide::{Build, Crate, Toolchain, Workspace, WorkspaceBuilder};
+use sha2::{Digest, Sha256};
 use std::borrow::Cow;
-use std::path::Path;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
+use storage::{self, Blob, Storage};
 use utils::{copy_doc_dir, parse_rustc_version, CargoMetadata};
+use postgres::Connection;
+use regex::Regex;
 use Metadata;
 
 static USER_AGENT: &str = "docs.rs builder (https://github.com/rust-lang/docs.rs)";
 static DEFAULT_RUSTWIDE_WORKSPACE: &str = ".rustwide";
+/// Image the builds run in, which is the default image of rustwide
+static BUILD_IMAGE: &str = "rustops/crates-build-env";
 
 static TARGETS: &[&str] = &[
     "i686-apple-darwin",
@@ -58,57 +78,318 @@ static ESSENTIAL_FILES_UNVERSIONED: &[&str] = &[
 static DUMMY_CRATE_NAME: &str = "acme-client";
 static DUMMY_CRATE_VERSION: &str = "0.0.0";
 
+lazy_static! {
+    /// Builders sharing a workspace build with the toolchain at the same time (read lock), but
+    /// it can only be updated when no build is running (write lock).
+    static ref TOOLCHAIN_LOCK: RwLock<()> = RwLock::new(());
+
+    /// Targets requested by crates are installed while other builders are running, one at a
+    /// time.
+    static ref TARGET_INSTALL_LOCK: Mutex<()> = Mutex::new(());
+
+    /// Images with the system dependencies requested by crates are built one at a time, so
+    /// builders requesting the same packages don't build the same image.
+    static ref IMAGE_BUILD_LOCK: Mutex<()> = Mutex::new(());
+}
+
 pub struct RustwideBuilder {
     workspace: Workspace,
+    workspace_path: PathBuf,
+    /// Workspaces sharing the directory of `workspace` but building in an image with system
+    /// dependencies installed, by image name
+    system_deps_workspaces: HashMap<String, Workspace>,
     toolchain: Toolchain,
+    toolchain_name: String,
+    /// Storage the documentation is uploaded into, builders of local crates don't have one
+    storage: Option<Arc<dyn Storage>>,
     rustc_version: String,
+    /// rustc versions of the toolchains installed in addition to the default one, for build
+    /// overrides and crates requesting them, by toolchain name
+    extra_toolchains: HashMap<String, String>,
+    build_dir_prefix: String,
+    worker_name: String,
 }
 
 impl RustwideBuilder {
     pub fn init() -> Result<Self> {
+        let builder = RustwideBuilder::new(Some(storage::from_env()?))?;
+        builder.workspace.purge_all_build_dirs()?;
+        Ok(builder)
+    }
+
+    /// Creates a builder for `build_local`, which doesn't need a database or a storage. The build
+    /// directories of the other builders using the workspace are kept.
+    pub fn init_local() -> Result<Self> {
+        RustwideBuilder::new(None)
+    }
+
+    fn new(storage: Option<Arc<dyn Storage>>) -> Result<Self> {
         let env_workspace_path = ::std::env::var("CRATESFYI_RUSTWIDE_WORKSPACE");
         let workspace_path = env_workspace_path
             .as_ref()
             .map(|v| v.as_str())
             .unwrap_or(DEFAULT_RUSTWIDE_WORKSPACE);
-        let workspace = WorkspaceBuilder::new(Path::new(workspace_path), USER_AGENT).init()?;
-        workspace.purge_all_build_dirs()?;
+        let workspace = WorkspaceBuilder::new(Path::new(workspace_path), USER_AGENT)
+            .sandbox_image(SandboxImage::remote(BUILD_IMAGE)?)
+            .init()?;
 
-        let toolchain_name = std::env::var("CRATESFYI_TOOLCHAIN")
-            .map(|t| Cow::Owned(t))
-            .unwrap_or_else(|_| Cow::Borrowed("nightly"));
-
-        let toolchain = Toolchain::Dist {
-            name: toolchain_name,
+        // the toolchain pinned with `cratesfyi build set-toolchain` takes precedence
+        let toolchain_name = match pinned_toolchain() {
+            Some(name) => name,
+            None => std::env::var("CRATESFYI_TOOLCHAIN").unwrap_or_else(|_| "nightly".to_owned()),
         };
 
         Ok(RustwideBuilder {
             workspace,
-            toolchain,
+            workspace_path: PathBuf::from(workspace_path),
+            system_deps_workspaces: HashMap::new(),
+            toolchain: dist_toolchain(&toolchain_name),
+            toolchain_name,
+            storage,
             rustc_version: String::new(),
+            extra_toolchains: HashMap::new(),
+            build_dir_prefix: String::new(),
+            worker_name: default_worker_name(),
         })
     }
 
+    /// Sets the worker this builder belongs to, so that its build directories don't clash with
+    /// the ones of other workers using the same workspace.
+    pub fn set_worker(&mut self, worker: usize) {
+        self.build_dir_prefix = format!("worker-{}-", worker);
+        self.worker_name = format!("{}/{}", default_worker_name(), worker);
+    }
+
+    /// Name identifying this builder in the build queue
+    pub(crate) fn worker_name(&self) -> &str {
+        &self.worker_name
+    }
+
+    /// Pins the default toolchain of every builder. The toolchain is installed and its essential
+    /// files are built first, so a broken toolchain is never pinned.
+    pub fn set_toolchain(&mut self, name: &str) -> Result<()> {
+        {
+            let _lock = TOOLCHAIN_LOCK.write().unwrap_or_else(|e| e.into_inner());
+            self.toolchain = dist_toolchain(name);
+            self.toolchain_name = name.to_owned();
+            self.toolchain.install(&self.workspace)?;
+            for target in TARGETS {
+                self.toolchain.add_target(&self.workspace, target)?;
+            }
+            self.add_essential_files()?;
+        }
+
+        set_default_toolchain(&connect_db()?, name)?;
+        info!("pinned toolchain {} ({})", name, self.rustc_version);
+        Ok(())
+    }
+
+    /// Installs a toolchain crates can request in addition to the default one
+    pub fn add_toolchain(&mut self, name: &str) -> Result<()> {
+        let mut overrides = BuildOverrides::new(DUMMY_CRATE_NAME, ALL_VERSIONS);
+        overrides.toolchain = Some(name.to_owned());
+        self.prepare_overrides(&overrides)?;
+
+        let conn = connect_db()?;
+        let mut toolchains = get_extra_toolchains(&conn)?;
+        if !toolchains.iter().any(|t| t == name) {
+            toolchains.push(name.to_owned());
+            set_extra_toolchains(&conn, &toolchains)?;
+        }
+        Ok(())
+    }
+
+    /// Stops allowing crates to request a toolchain. It's kept installed for builds using it
+    /// through build overrides.
+    pub fn remove_toolchain(&self, name: &str) -> Result<()> {
+        let conn = connect_db()?;
+        let mut toolchains = get_extra_toolchains(&conn)?;
+        toolchains.retain(|t| t != name);
+        set_extra_toolchains(&conn, &toolchains)
+    }
+
+    /// Returns the default toolchain and the toolchains crates can request
+    pub fn toolchains(&self) -> Result<(String, Vec<String>)> {
+        Ok((self.toolchain_name.clone(), get_extra_toolchains(&connect_db()?)?))
+    }
+
+    /// Allows crates to install a system package with the `dependencies` key of their metadata
+    pub fn add_system_dependency(&self, name: &str) -> Result<()> {
+        if !system_deps::is_valid_package_name(name) {
+            bail!("{} is not a valid package name", name);
+        }
+        let conn = connect_db()?;
+        let mut dependencies = get_allowed_dependencies(&conn)?;
+        if !dependencies.iter().any(|d| d == name) {
+            dependencies.push(name.to_owned());
+            dependencies.sort();
+            set_allowed_dependencies(&conn, &dependencies)?;
+        }
+        Ok(())
+    }
+
+    /// Stops allowing crates to install a system package. The images it's installed in are
+    /// kept until they're removed with `docker image rm`.
+    pub fn remove_system_dependency(&self, name: &str) -> Result<()> {
+        let conn = connect_db()?;
+        let mut dependencies = get_allowed_dependencies(&conn)?;
+        dependencies.retain(|d| d != name);
+        set_allowed_dependencies(&conn, &dependencies)
+    }
+
+    /// Returns the system packages crates are allowed to install
+    pub fn system_dependencies(&self) -> Result<Vec<String>> {
+        get_allowed_dependencies(&connect_db()?)
+    }
+
+    /// Installs the system dependencies requested by a crate which are allowed into a derived
+    /// image, and returns the workspace building in it. The default workspace is returned if no
+    /// dependency is allowed, or if the image couldn't be built.
+    fn prepare_system_deps(
+        &mut self,
+        mut dependencies: SystemDependencies,
+        steps: &mut BuildSteps,
+    ) -> Result<(Workspace, SystemDependencies)> {
+        if dependencies.allowed.is_empty() {
+            return Ok((self.workspace.clone(), dependencies));
+        }
+
+        let workspace = match steps.run("install system dependencies", || {
+            self.system_deps_workspace(&dependencies.allowed)
+        }) {
+            Ok(workspace) => workspace,
+            Err(e) => {
+                warn!("failed to install system dependencies: {}", e);
+                dependencies.error = Some(e.to_string());
+                self.workspace.clone()
+            }
+        };
+        Ok((workspace, dependencies))
+    }
+
+    /// Returns the workspace building in an image with `packages` installed. It shares the
+    /// directory, and so the toolchains, of the default workspace.
+    fn system_deps_workspace(&mut self, packages: &[String]) -> Result<Workspace> {
+        let image = {
+            let _lock = IMAGE_BUILD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            build_image(BUILD_IMAGE, packages)?
+        };
+        if let Some(workspace) = self.system_deps_workspaces.get(&image) {
+            return Ok(workspace.clone());
+        }
+
+        let workspace = WorkspaceBuilder::new(&self.workspace_path, USER_AGENT)
+            .sandbox_image(SandboxImage::local(&image)?)
+            .init()?;
+        self.system_deps_workspaces.insert(image, workspace.clone());
+        Ok(workspace)
+    }
+
     fn update_toolchain(&mut self) -> Result<()> {
+        let _lock = TOOLCHAIN_LOCK.write().unwrap_or_else(|e| e.into_inner());
+        if self.install_toolchain()? {
+            self.add_essential_files()?;
+        }
+        Ok(())
+    }
+
+    /// Installs the default toolchain, following its pin, and returns whether its version
+    /// changed. The caller holds the write lock of the toolchains.
+    fn install_toolchain(&mut self) -> Result<bool> {
+        // the default toolchain can be pinned to another one while builders are running
+        if let Some(name) = pinned_toolchain() {
+            self.toolchain = dist_toolchain(&name);
+            self.toolchain_name = name;
+        }
+
         // Ignore errors if detection fails.
-        let old_version = self.detect_rustc_version().ok();
+        let old_version = self.detect_rustc_version(&self.toolchain).ok();
 
         self.toolchain.install(&self.workspace)?;
         for target in TARGETS {
             self.toolchain.add_target(&self.workspace, target)?;
         }
-        self.rustc_version = self.detect_rustc_version()?;
+        self.rustc_version = self.detect_rustc_version(&self.toolchain)?;
 
-        if old_version.as_ref().map(|s| s.as_str()) != Some(&self.rustc_version) {
-            self.add_essential_files()?;
+        Ok(old_version.as_ref().map(|s| s.as_str()) != Some(&self.rustc_version))
+    }
+
+    /// Installs the toolchain requested by the overrides of a release
+    fn prepare_overrides(&mut self, overrides: &BuildOverrides) -> Result<()> {
+        let _lock = TOOLCHAIN_LOCK.write().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(ref name) = overrides.toolchain {
+            if !self.extra_toolchains.contains_key(name) {
+                info!("installing toolchain {} requested by build overrides", name);
+                let toolchain = dist_toolchain(name);
+                toolchain.install(&self.workspace)?;
+                for target in TARGETS {
+                    toolchain.add_target(&self.workspace, target)?;
+                }
+                let rustc_version = self.detect_rustc_version(&toolchain)?;
+                self.extra_toolchains.insert(name.clone(), rustc_version);
+
+                // local builds serve the essential files of their own output
+                if self.storage.is_some() {
+                    let mut essential_files = BuildOverrides::new(DUMMY_CRATE_NAME, ALL_VERSIONS);
+                    essential_files.toolchain = Some(name.clone());
+                    if let Err(e) = self.upload_essential_files(&essential_files) {
+                        self.extra_toolchains.remove(name);
+                        return Err(e);
+                    }
+                }
+            }
         }
 
         Ok(())
     }
 
-    fn detect_rustc_version(&self) -> Result<String> {
+    /// Installs a target which isn't installed with the toolchain, because a crate requested it
+    fn install_target(&self, toolchain: &Toolchain, target: &str) -> Result<()> {
+        let _lock = TARGET_INSTALL_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        debug!("installing target {}", target);
+        toolchain
+            .add_target(&self.workspace, target)
+            .with_context(|_| format!("couldn't install target {}", target))?;
+        Ok(())
+    }
+
+    /// Returns the toolchain requested by a crate in its metadata, if it's one of the extra
+    /// toolchains. Other toolchains are ignored, and the crate is built with the default one.
+    fn requested_toolchain(
+        &self,
+        conn: &Connection,
+        metadata: &Metadata,
+    ) -> Result<Option<String>> {
+        let requested = match metadata.toolchain {
+            Some(ref requested) if *requested != self.toolchain_name => requested,
+            _ => return Ok(None),
+        };
+
+        if get_extra_toolchains(conn)?.contains(requested) {
+            Ok(Some(requested.clone()))
+        } else {
+            warn!("toolchain {} is not available, using the default toolchain", requested);
+            Ok(None)
+        }
+    }
+
+    /// Returns the toolchain a release is built with, and its rustc version
+    fn toolchain_for(&self, overrides: &BuildOverrides) -> Result<(Toolchain, String)> {
+        match overrides.toolchain {
+            Some(ref name) => {
+                let rustc_version = self.extra_toolchains
+                    .get(name)
+                    .ok_or_else(|| format_err!("toolchain {} is not installed", name))?;
+                Ok((dist_toolchain(name), rustc_version.clone()))
+            }
+            None => Ok((self.toolchain.clone(), self.rustc_version.clone())),
+        }
+    }
+
+    fn detect_rustc_version(&self, toolchain: &Toolchain) -> Result<String> {
         info!("detecting rustc's version...");
-        let res = Command::new(&self.workspace, self.toolchain.rustc())
+        let res = Command::new(&self.workspace, toolchain.rustc())
             .args(&["--version"])
             .log_output(false)
             .run_capture()?;
@@ -124,17 +405,24 @@ impl RustwideBuilder {
     }
 
     pub fn add_essential_files(&mut self) -> Result<()> {
-        self.rustc_version = self.detect_rustc_version()?;
-        let rustc_version = parse_rustc_version(&self.rustc_version)?;
+        self.rustc_version = self.detect_rustc_version(&self.toolchain)?;
+        self.upload_essential_files(&BuildOverrides::new(DUMMY_CRATE_NAME, ALL_VERSIONS))
+    }
+
+    /// Builds a dummy crate with the toolchain of `overrides`, and uploads the rustdoc files
+    /// shared by every crate documented with it
+    fn upload_essential_files(&self, overrides: &BuildOverrides) -> Result<()> {
+        let (toolchain, full_rustc_version) = self.toolchain_for(overrides)?;
+        let rustc_version = parse_rustc_version(&full_rustc_version)?;
 
         info!("building a dummy crate to get essential files");
 
         let conn = connect_db()?;
-        let limits = Limits::for_crate(&conn, DUMMY_CRATE_NAME)?;
+        let limits = Limits::for_crate(&conn, DUMMY_CRATE_NAME, None)?;
 
         let mut build_dir = self
             .workspace
-            .build_dir(&format!("essential-files-{}", rustc_version));
+            .build_dir(&format!("{}essential-files-{}", self.build_dir_prefix, rustc_version));
         build_dir.purge()?;
 
         // acme-client-0.0.0 is an empty library crate and it will always build
@@ -146,14 +434,15 @@ impl RustwideBuilder {
             .enable_networking(limits.networking());
 
         build_dir
-            .build(&self.toolchain, &krate, sandbox)
+            .build(&toolchain, &krate, sandbox)
             .run(|build| {
-                let res = self.execute_build(None, build, &limits)?;
+                let res =
+                    self.execute_build(None, build, &limits, overrides, &mut BuildSteps::new())?;
                 if !res.successful {
-                    bail!("failed to build dummy crate for {}", self.rustc_version);
+                    bail!("failed to build dummy crate for {}", full_rustc_version);
                 }
 
-                info!("copying essential files for {}", self.rustc_version);
+                info!("copying essential files for {}", full_rustc_version);
                 let source = build.host_target_dir().join(&res.target).join("doc");
                 let dest = ::tempdir::TempDir::new("essential-files")?;
 
@@ -179,12 +468,15 @@ impl RustwideBuilder {
                     })?;
                 }
 
-                add_path_into_database(&conn, "", &dest)?;
-                conn.query(
-                    "INSERT INTO config (name, value) VALUES ('rustc_version', $1) \
-                     ON CONFLICT (name) DO UPDATE SET value = $1;",
-                    &[&self.rustc_version.to_json()],
-                )?;
+                add_path_into_database(self.storage()?, "", &dest)?;
+                // the website only links to the essential files of the default toolchain
+                if overrides.toolchain.is_none() {
+                    conn.query(
+                        "INSERT INTO config (name, value) VALUES ('rustc_version', $1) \
+                         ON CONFLICT (name) DO UPDATE SET value = $1;",
+                        &[&full_rustc_version.to_json()],
+                    )?;
+                }
 
                 Ok(())
             })?;
@@ -194,23 +486,81 @@ impl RustwideBuilder {
         Ok(())
     }
 
-    pub fn build_world(&mut self, doc_builder: &mut DocBuilder) -> Result<()> {
+    /// Builds the releases of the index matching `filter`. The latest version of every crate is
+    /// built first, then the older ones unless `build_only_latest_version` is set.
+    ///
+    /// The progress is recorded in the database, and an unfinished run is resumed instead of
+    /// starting a new one, unless `restart` is set.
+    pub fn build_world(
+        &mut self,
+        doc_builder: &mut DocBuilder,
+        filter: &WorldFilter,
+        restart: bool,
+    ) -> Result<()> {
+        let conn = connect_db()?;
+
+        let selection = filter.selection(doc_builder.options());
+        let run_id = match unfinished_world_run(&conn)? {
+            Some((id, description, stored)) if !restart => {
+                // a different set of releases would be built than the one asked for
+                if stored != selection {
+                    bail!("build world run {} ({}) was started with other filters, pass the same \
+                           ones to resume it or --restart to start a new run",
+                          id,
+                          description);
+                }
+                info!("resuming build world run {}", id);
+                id
+            }
+            previous => {
+                if let Some((id, _, _)) = previous {
+                    info!("abandoning build world run {}", id);
+                    finish_world_run(&conn, id)?;
+                }
+                let description = filter.describe();
+                let releases = filter.select_releases(doc_builder.options())?;
+                let id = start_world_run(&conn, &description, &selection, &releases)?;
+                info!("started build world run {} ({}) with {} releases",
+                      id, description, releases.len());
+                id
+            }
+        };
+
         let mut count = 0;
-        crates_from_path(
-            &doc_builder.options().crates_io_index_path.clone(),
-            &mut |name, version| {
-                match self.build_package(doc_builder, name, version) {
-                    Ok(status) => {
-                        count += 1;
-                        if status && count % 10 == 0 {
-                            let _ = doc_builder.save_cache();
+        while let Some((position, name, version)) = next_world_release(&conn, run_id)? {
+            let start = Instant::now();
+            let (status, error) = if !doc_builder.should_build(&name, &version) {
+                (WorldStatus::Skipped, None)
+            } else {
+                match self.build_package(doc_builder, &name, &version) {
+                    Ok(true) => (WorldStatus::Built, None),
+                    Ok(false) => (WorldStatus::Failed, None),
+                    Err(err) => {
+                        warn!("failed to build package {} {}: {}", name, version, err);
+                        // stop at the first failure to investigate it, the run is resumed from
+                        // this release
+                        if doc_builder.options().debug {
+                            return Err(err);
                         }
+                        (WorldStatus::Failed, Some(err.to_string()))
                     }
-                    Err(err) => warn!("failed to build package {} {}: {}", name, version, err),
                 }
-                doc_builder.add_to_cache(name, version);
-            },
-        )
+            };
+            let elapsed = start.elapsed();
+            let duration_ms = elapsed.as_secs() as i64 * 1000 + elapsed.subsec_millis() as i64;
+            record_world_release(&conn, run_id, position, status, duration_ms, error)?;
+
+            if status != WorldStatus::Skipped {
+                count += 1;
+                if count % 10 == 0 {
+                    let _ = doc_builder.save_cache();
+                }
+            }
+            doc_builder.add_to_cache(&name, &version);
+        }
+
+        finish_world_run(&conn, run_id)?;
+        Ok(())
     }
 
     pub fn build_package(
@@ -223,109 +573,383 @@ impl RustwideBuilder {
             return Ok(false);
         }
 
+        let conn = connect_db()?;
+        let mut overrides = BuildOverrides::for_release(&conn, name, Some(version))?;
+        let limits = Limits::from_overrides(&overrides);
+
         self.update_toolchain()?;
 
         info!("building package {} {}", name, version);
 
-        let conn = connect_db()?;
-        let limits = Limits::for_crate(&conn, name)?;
-
-        let mut build_dir = self.workspace.build_dir(&format!("{}-{}", name, version));
+        let build_dir_name = format!("{}{}-{}", self.build_dir_prefix, name, version);
+        let mut build_dir = self.workspace.build_dir(&build_dir_name);
         build_dir.purge()?;
 
         let krate = Crate::crates_io(name, version);
-        krate.fetch(&self.workspace)?;
+        let mut steps = BuildSteps::new();
+        steps.run("fetch", || krate.fetch(&self.workspace))?;
+
+        // The build is started again if the crate requests another toolchain, or system
+        // dependencies which have to be installed in the build image
+        let allowed_deps = get_allowed_dependencies(&conn)?;
+        let mut workspace = self.workspace.clone();
+        let mut system_deps: Option<SystemDependencies> = None;
+        let res = loop {
+            self.prepare_overrides(&overrides)?;
+            let _lock = TOOLCHAIN_LOCK.read().unwrap_or_else(|e| e.into_inner());
+            let (toolchain, _) = self.toolchain_for(&overrides)?;
+
+            let sandbox = SandboxBuilder::new()
+                .memory_limit(Some(limits.memory()))
+                .enable_networking(limits.networking());
+
+            let outcome = workspace
+                .build_dir(&build_dir_name)
+                .build(&toolchain, &krate, sandbox)
+                .run(|build| {
+                    let metadata = Metadata::from_source_dir(&build.host_source_dir())?;
+                    // toolchains pinned by overrides take precedence over the requested ones
+                    if overrides.toolchain.is_none() {
+                        if let Some(requested) = self.requested_toolchain(&conn, &metadata)? {
+                            return Ok(BuildOutcome::SwitchToolchain(requested));
+                        }
+                    }
+                    let deps_log = match system_deps {
+                        Some(ref dependencies) => dependencies.log(),
+                        None => {
+                            let requested = metadata.dependencies.unwrap_or_default();
+                            let dependencies = SystemDependencies::new(&requested, &allowed_deps);
+                            // only the allowed packages are worth building the crate again
+                            if !dependencies.allowed.is_empty() {
+                                return Ok(BuildOutcome::InstallSystemDeps(dependencies));
+                            }
+                            dependencies.log()
+                        }
+                    };
+
+                    let mut files_list = None;
+                    let mut has_docs = false;
+                    let mut successful_targets = Vec::new();
+                    let mut identical_targets = Vec::new();
+
+                    // Do an initial build and then copy the sources in the database
+                    let mut res =
+                        self.execute_build(None, &build, &limits, &overrides, &mut steps)?;
+                    res.build_log.insert_str(0, &deps_log);
+                    if res.successful {
+                        debug!("adding sources into database");
+                        let prefix = format!("sources/{}/{}", name, version);
+                        files_list = Some(steps.run("upload sources", || {
+                            add_path_into_archive(self.storage()?, &prefix, build.host_source_dir())
+                        })?);
+
+                        has_docs = build
+                            .host_target_dir()
+                            .join(&res.target)
+                            .join("doc")
+                            .join(name.replace("-", "_"))
+                            .is_dir();
+                    }
+
+                    if has_docs {
+                        debug!("adding documentation for the default target to the database");
+                        self.copy_docs(
+                            &doc_builder,
+                            &build.host_target_dir(),
+                            name,
+                            version,
+                            &res.target,
+                            &res.rustc_version,
+                            true,
+                        )?;
+
+                        let (targets, identical) = self.build_targets(
+                            doc_builder,
+                            &build,
+                            &res,
+                            &limits,
+                            &overrides,
+                            &mut steps,
+                            name,
+                            version,
+                        )?;
+                        successful_targets = targets;
+                        identical_targets = identical;
+
+                        steps.run("upload docs", || self.upload_docs(doc_builder, name, version))?;
 
+                        // the documentation is still browsable without the offline archive
+                        let static_dir = build.host_target_dir().join(&res.target).join("doc");
+                        if let Err(e) = steps.run("upload offline docs", || {
+                            self.upload_offline_docs(doc_builder, &static_dir, name, version)
+                        }) {
+                            warn!("failed to upload offline docs of {} {}: {}", name, version, e);
+                        }
+                    }
+
+                    let has_examples = build.host_source_dir().join("examples").is_dir();
+                    let release_id = add_package_into_database(
+                        &conn,
+                        res.cargo_metadata.root(),
+                        &build.host_source_dir(),
+                        &res,
+                        files_list,
+                        successful_targets,
+                        identical_targets,
+                        has_docs,
+                        has_examples,
+                    )?;
+                    let build_id = add_build_into_database(&conn, &release_id, &res)?;
+                    add_build_steps_into_database(&conn, build_id, &steps)?;
+
+                    doc_builder.add_to_cache(name, version);
+                    Ok(BuildOutcome::Built(res))
+                })?;
+
+            match outcome {
+                BuildOutcome::Built(res) => break res,
+                BuildOutcome::SwitchToolchain(requested) => {
+                    info!("building package {} {} with toolchain {}", name, version, requested);
+                    overrides.toolchain = Some(requested);
+                    build_dir.purge()?;
+                }
+                BuildOutcome::InstallSystemDeps(dependencies) => {
+                    let (deps_workspace, dependencies) =
+                        self.prepare_system_deps(dependencies, &mut steps)?;
+                    info!("building package {} {} with system dependencies {}",
+                          name,
+                          version,
+                          dependencies.allowed.join(", "));
+                    workspace = deps_workspace;
+                    system_deps = Some(dependencies);
+                    build_dir.purge()?;
+                }
+            }
+        };
+
+        build_dir.purge()?;
+        krate.purge_from_cache(&self.workspace)?;
+        Ok(res.successful)
+    }
+
+    /// Builds the documentation of a crate from a local directory with the same settings as the
+    /// crates of the registry, to preview it before publishing. The documentation is copied into
+    /// the destination directory, and uploaded into `storage` if it's set. Nothing is added into
+    /// the database.
+    ///
+    /// The database is only used, if one is reachable, to find the toolchains and the system
+    /// dependencies crates can request. Without it, the crate is built with the default
+    /// toolchain and without system dependencies.
+    pub fn build_local(
+        &mut self,
+        doc_builder: &DocBuilder,
+        path: &Path,
+        storage: Option<&dyn Storage>,
+    ) -> Result<LocalBuild> {
+        let conn = optional_db();
+        {
+            let _lock = TOOLCHAIN_LOCK.write().unwrap_or_else(|e| e.into_inner());
+            self.install_toolchain()?;
+        }
+
+        // overrides are set by docs.rs administrators for published crates
+        let mut overrides = BuildOverrides::new("", ALL_VERSIONS);
+        let limits = Limits::from_overrides(&overrides);
+        let metadata = Metadata::from_source_dir(path)?;
+        if let Some(ref conn) = conn {
+            overrides.toolchain = self.requested_toolchain(conn, &metadata)?;
+        }
+        self.prepare_overrides(&overrides)?;
+
+        info!("building local crate {}", path.display());
+
+        let mut steps = BuildSteps::new();
+        let requested_deps = metadata.dependencies.unwrap_or_default();
+        let allowed_deps = match conn {
+            Some(ref conn) => get_allowed_dependencies(conn)?,
+            None => Vec::new(),
+        };
+        let (workspace, system_deps) = self.prepare_system_deps(
+            SystemDependencies::new(&requested_deps, &allowed_deps),
+            &mut steps,
+        )?;
+
+        let mut build_dir = workspace.build_dir(&format!("{}local", self.build_dir_prefix));
+        build_dir.purge()?;
+
+        let krate = Crate::local(path);
+        let _lock = TOOLCHAIN_LOCK.read().unwrap_or_else(|e| e.into_inner());
+        let (toolchain, _) = self.toolchain_for(&overrides)?;
         let sandbox = SandboxBuilder::new()
             .memory_limit(Some(limits.memory()))
             .enable_networking(limits.networking());
 
-        let res = build_dir
-            .build(&self.toolchain, &krate, sandbox)
+        let local = build_dir
+            .build(&toolchain, &krate, sandbox)
             .run(|build| {
-                let mut files_list = None;
-                let mut has_docs = false;
-                let mut successful_targets = Vec::new();
+                let mut res = self.execute_build(None, &build, &limits, &overrides, &mut steps)?;
+                res.build_log.insert_str(0, &system_deps.log());
+                let (name, version) = {
+                    let root = res.cargo_metadata.root();
+                    (root.name.clone(), root.version.clone())
+                };
 
-                // Do an initial build and then copy the sources in the database
-                let res = self.execute_build(None, &build, &limits)?;
-                if res.successful {
-                    debug!("adding sources into database");
-                    let prefix = format!("sources/{}/{}", name, version);
-                    files_list = Some(add_path_into_database(
-                        &conn,
-                        &prefix,
-                        build.host_source_dir(),
-                    )?);
-
-                    has_docs = build
+                let has_docs = res.successful
+                    && build
                         .host_target_dir()
                         .join(&res.target)
                         .join("doc")
                         .join(name.replace("-", "_"))
                         .is_dir();
-                }
-
+                let mut successful_targets = Vec::new();
                 if has_docs {
-                    debug!("adding documentation for the default target to the database");
                     self.copy_docs(
-                        &doc_builder,
+                        doc_builder,
                         &build.host_target_dir(),
-                        name,
-                        version,
+                        &name,
+                        &version,
                         &res.target,
+                        &res.rustc_version,
                         true,
                     )?;
+                    successful_targets = self
+                        .build_targets(
+                            doc_builder,
+                            &build,
+                            &res,
+                            &limits,
+                            &overrides,
+                            &mut steps,
+                            &name,
+                            &version,
+                        )?
+                        .0;
 
-                    // Then build the documentation for all the targets
-                    for target in TARGETS {
-                        debug!("building package {} {} for {}", name, version, target);
-                        let target_res = self.execute_build(Some(target), &build, &limits)?;
-                        if target_res.successful {
-                            // Cargo is not giving any error and not generating documentation of some crates
-                            // when we use a target compile options. Check documentation exists before
-                            // adding target to successfully_targets.
-                            if build.host_target_dir().join(target).join("doc").is_dir() {
-                                debug!(
-                                    "adding documentation for target {} to the database",
-                                    target
-                                );
-                                self.copy_docs(
-                                    &doc_builder,
-                                    &build.host_target_dir(),
-                                    name,
-                                    version,
-                                    target,
-                                    false,
-                                )?;
-                                successful_targets.push(target.to_string());
+                    if let Some(storage) = storage {
+                        let prefix = format!("rustdoc/{}/{}", name, version);
+                        let doc_dir = doc_builder.options().destination.join(&name).join(&version);
+                        add_path_into_database(storage, &prefix, doc_dir)?;
+                        // the static files are shared by every crate on docs.rs, and only stored
+                        // when the toolchain is updated
+                        let static_dir = build.host_target_dir().join(&res.target).join("doc");
+                        let dest = ::tempdir::TempDir::new("local-static-files")?;
+                        for entry in fs::read_dir(&static_dir)? {
+                            let entry = entry?;
+                            if entry.file_type()?.is_file() {
+                                fs::copy(entry.path(), dest.path().join(entry.file_name()))?;
                             }
                         }
+                        add_path_into_database(storage, "", dest.path())?;
                     }
-
-                    self.upload_docs(doc_builder, &conn, name, version)?;
                 }
 
-                let has_examples = build.host_source_dir().join("examples").is_dir();
-                let release_id = add_package_into_database(
-                    &conn,
-                    res.cargo_metadata.root(),
-                    &build.host_source_dir(),
-                    &res,
-                    files_list,
-                    successful_targets,
+                Ok(LocalBuild {
+                    name,
+                    version,
                     has_docs,
-                    has_examples,
-                )?;
-                add_build_into_database(&conn, &release_id, &res)?;
-
-                doc_builder.add_to_cache(name, version);
-                Ok(res)
+                    default_target: res.target.clone(),
+                    successful_targets,
+                    result: res,
+                })
             })?;
 
         build_dir.purge()?;
-        krate.purge_from_cache(&self.workspace)?;
-        Ok(res.successful)
+        Ok(local)
+    }
+
+    /// Returns the storage the documentation is uploaded into
+    fn storage(&self) -> Result<&dyn Storage> {
+        match self.storage {
+            Some(ref storage) => Ok(&**storage),
+            None => bail!("builders of local crates don't upload into the storage"),
+        }
+    }
+
+    /// Builds the documentation for all the targets requested by the crate, and the extra
+    /// targets requested by the overrides, after the default one. Returns the targets with
+    /// documentation, and the ones whose documentation is identical to the default target.
+    ///
+    /// Platform independent crates aren't built again: their documentation is the same for
+    /// every target. The other crates are built for every target, and the documentation
+    /// identical to the default target's one isn't stored.
+    fn build_targets(
+        &self,
+        doc_builder: &DocBuilder,
+        build: &Build,
+        default: &BuildResult,
+        limits: &Limits,
+        overrides: &BuildOverrides,
+        steps: &mut BuildSteps,
+        name: &str,
+        version: &str,
+    ) -> Result<(Vec<String>, Vec<String>)> {
+        let mut successful_targets = Vec::new();
+        let mut identical_targets = Vec::new();
+
+        let metadata = Metadata::from_source_dir(&build.host_source_dir())?;
+        let platform_independent =
+            is_platform_independent(&build.host_source_dir(), &default.cargo_metadata)?;
+        let default_docs_hash =
+            hash_doc_dir(&build.host_target_dir().join(&default.target).join("doc"))?;
+        let mut targets: Vec<&str> = match metadata.targets {
+            Some(ref targets) => targets.iter().map(|t| t.as_str()).collect(),
+            None => TARGETS.to_vec(),
+        };
+        for target in overrides.extra_targets.iter().flat_map(|t| t.iter()) {
+            if !targets.contains(&target.as_str()) {
+                targets.push(target);
+            }
+        }
+
+        for target in targets {
+            if platform_independent || target == default.target {
+                debug!("documentation for target {} is identical to the default target", target);
+                identical_targets.push(target.to_string());
+                successful_targets.push(target.to_string());
+                continue;
+            }
+
+            debug!("building package {} {} for {}", name, version, target);
+            let target_res =
+                match self.execute_build(Some(target), build, limits, overrides, steps) {
+                    Ok(res) => res,
+                    Err(e) => {
+                        warn!("failed to build {} {} for {}: {}", name, version, target, e);
+                        continue;
+                    }
+                };
+            if target_res.successful {
+                // Cargo is not giving any error and not generating documentation of some crates
+                // when we use a target compile options. Check documentation exists before adding
+                // target to successfully_targets.
+                let target_docs = build.host_target_dir().join(target).join("doc");
+                if target_docs.is_dir() {
+                    // Crates using target specific code can still have the same documentation
+                    // for some targets, don't store it twice
+                    if hash_doc_dir(&target_docs)? == default_docs_hash {
+                        debug!(
+                            "documentation for target {} is identical to the default target",
+                            target
+                        );
+                        identical_targets.push(target.to_string());
+                    } else {
+                        debug!("adding documentation for target {} to the database", target);
+                        self.copy_docs(
+                            doc_builder,
+                            &build.host_target_dir(),
+                            name,
+                            version,
+                            target,
+                            &default.rustc_version,
+                            false,
+                        )?;
+                    }
+                    successful_targets.push(target.to_string());
+                }
+            }
+        }
+
+        Ok((successful_targets, identical_targets))
     }
 
     fn execute_build(
@@ -333,25 +957,37 @@ impl RustwideBuilder {
         target: Option<&str>,
         build: &Build,
         limits: &Limits,
+        overrides: &BuildOverrides,
+        steps: &mut BuildSteps,
     ) -> Result<BuildResult> {
-        let metadata = Metadata::from_source_dir(&build.host_source_dir())?;
-        let cargo_metadata =
-            CargoMetadata::load(&self.workspace, &self.toolchain, &build.host_source_dir())?;
+        let (toolchain, rustc_version) = self.toolchain_for(overrides)?;
+        let (metadata, metadata_warnings) =
+            Metadata::from_source_dir_with_warnings(&build.host_source_dir())?;
 
         let target = if let Some(target) = target {
             target
         } else if let Some(target) = metadata.default_target.as_ref().map(|s| s.as_str()) {
             target
+        } else if let Some(target) = metadata.targets.as_ref().and_then(|t| t.first()) {
+            target.as_str()
         } else {
             DEFAULT_TARGET
         }
         .to_string();
 
+        if !TARGETS.contains(&target.as_str()) {
+            self.install_target(&toolchain, &target)?;
+        }
+
+        let cargo_metadata = steps.run(&format!("cargo metadata ({})", target), || {
+            CargoMetadata::load(&self.workspace, &toolchain, &build.host_source_dir())
+        })?;
+
         let mut rustdoc_flags: Vec<String> = vec![
             "-Z".to_string(),
             "unstable-options".to_string(),
             "--resource-suffix".to_string(),
-            format!("-{}", parse_rustc_version(&self.rustc_version)?),
+            format!("-{}", parse_rustc_version(&rustc_version)?),
             "--static-root-path".to_string(),
             "/".to_string(),
             "--disable-per-crate-search".to_string(),
@@ -375,7 +1011,13 @@ impl RustwideBuilder {
             "--target".to_owned(),
             target.to_owned(),
         ];
-        if let Some(features) = &metadata.features {
+        let features: Vec<String> = metadata
+            .features
+            .iter()
+            .chain(overrides.features.iter())
+            .flat_map(|features| features.iter().cloned())
+            .collect();
+        if !features.is_empty() {
             cargo_args.push("--features".to_owned());
             cargo_args.push(features.join(" "));
         }
@@ -389,7 +1031,8 @@ impl RustwideBuilder {
         let mut storage = LogStorage::new(LevelFilter::Info);
         storage.set_max_size(limits.max_log_size());
 
-        let successful = logging::capture(&storage, || {
+        let start = Instant::now();
+        let result = logging::capture(&storage, || {
             build
                 .cargo()
                 .timeout(Some(limits.timeout()))
@@ -404,14 +1047,25 @@ impl RustwideBuilder {
                 .env("RUSTDOCFLAGS", rustdoc_flags.join(" "))
                 .args(&cargo_args)
                 .run()
-                .is_ok()
         });
+        let mut build_log = storage.to_string();
+        steps.push(
+            &format!("cargo doc ({})", target),
+            start.elapsed(),
+            result.as_ref().err().map(|e| e.to_string()),
+            &build_log,
+        );
+        let failure_kind = result.as_ref().err().map(|e| FailureKind::classify(e, &build_log));
+        for warning in &metadata_warnings {
+            build_log.push_str(&format!("warning: [package.metadata.docs.rs] {}\n", warning));
+        }
 
         Ok(BuildResult {
-            build_log: storage.to_string(),
-            rustc_version: self.rustc_version.clone(),
+            build_log,
+            rustc_version,
             docsrs_version: format!("docsrs {}", ::BUILD_VERSION),
-            successful,
+            successful: result.is_ok(),
+            failure_kind,
             cargo_metadata,
             target: target.to_string(),
         })
@@ -424,6 +1078,7 @@ impl RustwideBuilder {
         name: &str,
         version: &str,
         target: &str,
+        rustc_version: &str,
         is_default_target: bool,
     ) -> Result<()> {
         let source = target_dir.join(target);
@@ -439,14 +1094,13 @@ impl RustwideBuilder {
         }
 
         info!("{} {}", source.display(), dest.display());
-        copy_doc_dir(source, dest, self.rustc_version.trim())?;
+        copy_doc_dir(source, dest, rustc_version.trim())?;
         Ok(())
     }
 
     fn upload_docs(
         &self,
         doc_builder: &DocBuilder,
-        conn: &Connection,
         name: &str,
         version: &str,
     ) -> Result<()> {
@@ -454,16 +1108,195 @@ impl RustwideBuilder {
         let prefix = format!("rustdoc/{}/{}", name, version);
         let database_prefix =
             Path::new(&doc_builder.options().destination).join(format!("{}/{}", name, version));
-        add_path_into_database(conn, &prefix, database_prefix)?;
+        add_path_into_archive(self.storage()?, &prefix, database_prefix)?;
+        Ok(())
+    }
+
+    /// Stores a tarball of the uploaded documentation, which can be browsed offline
+    fn upload_offline_docs(
+        &self,
+        doc_builder: &DocBuilder,
+        static_dir: &Path,
+        name: &str,
+        version: &str,
+    ) -> Result<()> {
+        debug!("Adding offline documentation into database");
+        let doc_dir = doc_builder.options().destination.join(name).join(version);
+        let content = build_offline_docs(&doc_dir, static_dir, name, version)?;
+        self.storage()?.store(&[Blob {
+            path: offline_docs_path(name, version),
+            mime: OFFLINE_DOCS_MIME.to_owned(),
+            date_updated: ::time::get_time(),
+            content,
+            compression: None,
+        }])?;
         Ok(())
     }
 }
 
+fn dist_toolchain(name: &str) -> Toolchain {
+    Toolchain::Dist {
+        name: Cow::Owned(name.to_owned()),
+    }
+}
+
+/// Connects to the database if one is configured and reachable
+fn optional_db() -> Option<Connection> {
+    if ::std::env::var_os("CRATESFYI_DATABASE_URL").is_none() {
+        return None;
+    }
+    match connect_db() {
+        Ok(conn) => Some(conn),
+        Err(e) => {
+            warn!("failed to connect to the database: {}", e);
+            None
+        }
+    }
+}
+
+/// Returns the toolchain pinned with `cratesfyi build set-toolchain`. Builders without a
+/// database, like the ones of `cratesfyi build local`, don't follow the pin.
+fn pinned_toolchain() -> Option<String> {
+    let conn = optional_db()?;
+    match get_default_toolchain(&conn) {
+        Ok(pinned) => pinned,
+        Err(e) => {
+            warn!("failed to read the pinned toolchain, using the default one: {}", e);
+            None
+        }
+    }
+}
+
+/// Returns a hash of the paths and contents of the files in a documentation directory
+fn hash_doc_dir(path: &Path) -> Result<String> {
+    let mut files = get_file_list(path)?;
+    files.sort();
+
+    let mut hasher = Sha256::new();
+    for file in &files {
+        let content = fs::read(path.join(file))?;
+        hasher.input(file.to_string_lossy().as_bytes());
+        hasher.input(&[0]);
+        hasher.input(content.len().to_string().as_bytes());
+        hasher.input(&[0]);
+        hasher.input(&content);
+    }
+    Ok(format!("{:x}", hasher.result()))
+}
+
+/// Returns whether the documentation of a crate is the same for every target: it has no build
+/// script, no target specific dependencies, and its sources don't use target specific `cfg`s
+fn is_platform_independent(source_dir: &Path, cargo_metadata: &CargoMetadata) -> Result<bool> {
+    let root = cargo_metadata.root();
+    if root.dependencies.iter().any(|dep| dep.target.is_some()) {
+        return Ok(false);
+    }
+    // build scripts can set cfgs depending on the target
+    if root.targets.iter().any(|target| target.kind.iter().any(|kind| kind == "custom-build")) {
+        return Ok(false);
+    }
+    Ok(!sources_use_target_cfg(source_dir)?)
+}
+
+/// Returns whether a Rust source file in `dir` or its subdirectories uses target specific `cfg`s
+fn sources_use_target_cfg(dir: &Path) -> Result<bool> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            if sources_use_target_cfg(&path)? {
+                return Ok(true);
+            }
+        } else if path.extension().map_or(false, |ext| ext == "rs") {
+            // sources which can't be read can't be checked either
+            let uses_cfg = fs::read_to_string(&path).map(|source| uses_target_cfg(&source));
+            if uses_cfg.unwrap_or(true) {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Returns whether a Rust source file contains a target specific `cfg`. Some matches aren't in
+/// a `cfg`, which only means the crate is built for every target.
+fn uses_target_cfg(source: &str) -> bool {
+    lazy_static! {
+        static ref TARGET_CFG: Regex = Regex::new(concat!(
+            r"\btarget_(os|family|arch|env|pointer_width|endian|vendor|feature|has_atomic)\b|",
+            r"\b(cfg!?|any|all|not)\s*\(\s*(windows|unix)\b|,\s*(windows|unix)\s*[,)]"
+        )).unwrap();
+    }
+    TARGET_CFG.is_match(source)
+}
+
+/// Returns `hostname:pid`, which identifies the builders of this process
+fn default_worker_name() -> String {
+    let mut buf = [0u8; 256];
+    let res = unsafe { ::libc::gethostname(buf.as_mut_ptr() as *mut ::libc::c_char, buf.len()) };
+    let len = if res == 0 { buf.iter().position(|&b| b == 0).unwrap_or(buf.len()) } else { 0 };
+    let hostname = String::from_utf8_lossy(&buf[..len]);
+    format!("{}:{}", hostname, ::std::process::id())
+}
+
+/// Result of `RustwideBuilder::build_local`
+pub struct LocalBuild {
+    pub name: String,
+    pub version: String,
+    pub has_docs: bool,
+    pub default_target: String,
+    /// Targets built in addition to the default one
+    pub successful_targets: Vec<String>,
+    result: BuildResult,
+}
+
+impl LocalBuild {
+    pub fn successful(&self) -> bool {
+        self.result.successful
+    }
+
+    pub fn build_log(&self) -> &str {
+        &self.result.build_log
+    }
+
+    pub fn failure_kind(&self) -> Option<FailureKind> {
+        self.result.failure_kind
+    }
+}
+
+/// Result of a build, which is interrupted if the crate requests another toolchain
+enum BuildOutcome {
+    Built(BuildResult),
+    SwitchToolchain(String),
+    /// The crate requested system dependencies which are allowed, but haven't been installed yet
+    InstallSystemDeps(SystemDependencies),
+}
+
 pub(crate) struct BuildResult {
     pub(crate) rustc_version: String,
     pub(crate) docsrs_version: String,
     pub(crate) build_log: String,
     pub(crate) successful: bool,
+    /// Cause of the failure of an unsuccessful build
+    pub(crate) failure_kind: Option<FailureKind>,
     target: String,
     cargo_metadata: CargoMetadata,
 }
+
+#[cfg(test)]
+mod test {
+    use super::uses_target_cfg;
+
+    #[test]
+    fn test_uses_target_cfg() {
+        assert!(uses_target_cfg("#[cfg(target_os = \"linux\")]\nmod linux;"));
+        assert!(uses_target_cfg("#[cfg(all(unix, not(target_env = \"musl\")))]"));
+        assert!(uses_target_cfg("#[cfg(windows)]\nuse winapi;"));
+        assert!(uses_target_cfg("if cfg!(unix) {}"));
+        assert!(uses_target_cfg("#[cfg(any(feature = \"std\", windows))]"));
+
+        assert!(!uses_target_cfg("#[cfg(feature = \"std\")]\npub mod io;"));
+        assert!(!uses_target_cfg("/// Splits the slice into windows of `n` elements"));
+        assert!(!uses_target_cfg("pub fn windows(&self) {}"));
+    }
+}
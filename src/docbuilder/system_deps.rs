@@ -0,0 +1,172 @@
+//! System packages installed into the build image for crates needing native libraries.
+//!
+//! Crates list the Debian packages they need with the `dependencies` key of
+//! `[package.metadata.docs.rs]`. Only the packages allowed by docs.rs administrators, which are
+//! stored in the `config` table, are installed. They are installed into an image derived from the
+//! build image, shared by every crate requesting the same packages.
+
+use docbuilder::toolchains::{get_config, set_config};
+use error::Result;
+use postgres::Connection;
+use regex::Regex;
+use rustc_serialize::json::ToJson;
+use sha2::{Digest, Sha256};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+
+const ALLOWED_SYSTEM_DEPENDENCIES: &'static str = "allowed_system_dependencies";
+
+
+/// Returns the packages crates are allowed to install
+pub(crate) fn get_allowed_dependencies(conn: &Connection) -> Result<Vec<String>> {
+    Ok(get_config(conn, ALLOWED_SYSTEM_DEPENDENCIES)?
+        .and_then(|value| {
+            value.as_array().map(|names| {
+                names.iter().filter_map(|name| name.as_string().map(|n| n.to_owned())).collect()
+            })
+        })
+        .unwrap_or_default())
+}
+
+
+pub(crate) fn set_allowed_dependencies(conn: &Connection, names: &[String]) -> Result<()> {
+    set_config(conn, ALLOWED_SYSTEM_DEPENDENCIES, names.to_json())
+}
+
+
+/// Returns whether `name` is a valid Debian package name, so it can't inject commands in the
+/// Dockerfile of the derived image
+pub(crate) fn is_valid_package_name(name: &str) -> bool {
+    lazy_static! {
+        static ref PACKAGE_NAME: Regex = Regex::new(r"^[a-z0-9][a-z0-9+.-]+$").unwrap();
+    }
+    PACKAGE_NAME.is_match(name)
+}
+
+
+/// System dependencies requested by a crate
+#[derive(Debug, Default)]
+pub(crate) struct SystemDependencies {
+    /// Requested packages which are allowed, sorted
+    pub(crate) allowed: Vec<String>,
+    /// Requested packages which aren't allowed
+    pub(crate) rejected: Vec<String>,
+    /// Why the allowed packages couldn't be installed
+    pub(crate) error: Option<String>,
+}
+
+impl SystemDependencies {
+    pub(crate) fn new(requested: &[String], allowed: &[String]) -> SystemDependencies {
+        let mut dependencies = SystemDependencies::default();
+        for name in requested {
+            if allowed.contains(name) {
+                dependencies.allowed.push(name.clone());
+            } else if !dependencies.rejected.contains(name) {
+                dependencies.rejected.push(name.clone());
+            }
+        }
+        dependencies.allowed.sort();
+        dependencies.allowed.dedup();
+        dependencies
+    }
+
+    /// Returns the lines added at the start of the build log
+    pub(crate) fn log(&self) -> String {
+        let mut log = String::new();
+        if !self.allowed.is_empty() {
+            match self.error {
+                Some(ref error) => {
+                    log.push_str(&format!("[docs.rs] failed to install system dependencies {}: \
+                                           {}\n",
+                                          self.allowed.join(", "),
+                                          error))
+                }
+                None => {
+                    log.push_str(&format!("[docs.rs] installed system dependencies: {}\n",
+                                          self.allowed.join(", ")))
+                }
+            }
+        }
+        if !self.rejected.is_empty() {
+            log.push_str(&format!("[docs.rs] system dependencies not allowed on docs.rs: {}\n",
+                                  self.rejected.join(", ")));
+        }
+        log
+    }
+}
+
+
+/// Builds the image with `packages` installed on top of `base_image`, unless it already exists,
+/// and returns its name. The name depends on the base image, so the derived images are built
+/// again when the build image is updated.
+pub(crate) fn build_image(base_image: &str, packages: &[String]) -> Result<String> {
+    let base_id = docker(&["image", "inspect", "--format", "{{.Id}}", base_image])?;
+    let mut hasher = Sha256::new();
+    hasher.input(base_id.trim().as_bytes());
+    for package in packages {
+        hasher.input(&[0]);
+        hasher.input(package.as_bytes());
+    }
+    let image = format!("docsrs-system-deps:{}", &format!("{:x}", hasher.result())[..16]);
+
+    if docker(&["image", "inspect", &image]).is_ok() {
+        return Ok(image);
+    }
+
+    info!("building image {} with {}", image, packages.join(", "));
+    let dockerfile = format!("FROM {}\n\
+                              RUN apt-get update && \
+                              apt-get install -y --no-install-recommends {} && \
+                              rm -rf /var/lib/apt/lists/*\n",
+                             base_image,
+                             packages.join(" "));
+    let mut child = Command::new("docker")
+        .args(&["build", "--tag", &image, "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    child.stdin.take().expect("stdin is piped").write_all(dockerfile.as_bytes())?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        bail!("building image {} failed: {}", image, String::from_utf8_lossy(&output.stderr));
+    }
+    Ok(image)
+}
+
+
+fn docker(args: &[&str]) -> Result<String> {
+    let output = Command::new("docker").args(args).output()?;
+    if !output.status.success() {
+        bail!("docker {} failed: {}", args[0], String::from_utf8_lossy(&output.stderr));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{is_valid_package_name, SystemDependencies};
+
+    #[test]
+    fn test_system_dependencies() {
+        let requested = vec!["libssl-dev".to_owned(), "libgtk-3-dev".to_owned(),
+                             "cmake".to_owned(), "libssl-dev".to_owned()];
+        let allowed = vec!["cmake".to_owned(), "libssl-dev".to_owned()];
+        let dependencies = SystemDependencies::new(&requested, &allowed);
+        assert_eq!(dependencies.allowed, vec!["cmake".to_owned(), "libssl-dev".to_owned()]);
+        assert_eq!(dependencies.rejected, vec!["libgtk-3-dev".to_owned()]);
+        assert_eq!(dependencies.log(),
+                   "[docs.rs] installed system dependencies: cmake, libssl-dev\n\
+                    [docs.rs] system dependencies not allowed on docs.rs: libgtk-3-dev\n");
+    }
+
+    #[test]
+    fn test_is_valid_package_name() {
+        assert!(is_valid_package_name("libstdc++-8-dev"));
+        assert!(is_valid_package_name("libgtk-3.0-dev"));
+        assert!(!is_valid_package_name("cmake && curl evil.example"));
+        assert!(!is_valid_package_name("-y"));
+    }
+}
@@ -0,0 +1,56 @@
+//! Toolchains used by the builders.
+//!
+//! The default toolchain is pinned in the `config` table, so it can be rolled forward without
+//! restarting the builders. Extra toolchains can be installed next to it, and crates can request
+//! one of them with the `toolchain` key of `[package.metadata.docs.rs]`.
+
+use error::Result;
+use postgres::Connection;
+use rustc_serialize::json::{Json, ToJson};
+
+
+const DEFAULT_TOOLCHAIN: &'static str = "toolchain";
+const EXTRA_TOOLCHAINS: &'static str = "extra_toolchains";
+
+
+/// Returns the pinned default toolchain, if one is pinned
+pub(crate) fn get_default_toolchain(conn: &Connection) -> Result<Option<String>> {
+    Ok(get_config(conn, DEFAULT_TOOLCHAIN)?
+        .and_then(|value| value.as_string().map(|name| name.to_owned())))
+}
+
+
+pub(crate) fn set_default_toolchain(conn: &Connection, name: &str) -> Result<()> {
+    set_config(conn, DEFAULT_TOOLCHAIN, name.to_json())
+}
+
+
+/// Returns the toolchains crates can request in addition to the default one
+pub(crate) fn get_extra_toolchains(conn: &Connection) -> Result<Vec<String>> {
+    Ok(get_config(conn, EXTRA_TOOLCHAINS)?
+        .and_then(|value| {
+            value.as_array().map(|names| {
+                names.iter().filter_map(|name| name.as_string().map(|n| n.to_owned())).collect()
+            })
+        })
+        .unwrap_or_default())
+}
+
+
+pub(crate) fn set_extra_toolchains(conn: &Connection, names: &[String]) -> Result<()> {
+    set_config(conn, EXTRA_TOOLCHAINS, names.to_json())
+}
+
+
+pub(crate) fn get_config(conn: &Connection, name: &str) -> Result<Option<Json>> {
+    let rows = conn.query("SELECT value FROM config WHERE name = $1", &[&name])?;
+    Ok(if rows.is_empty() { None } else { Some(rows.get(0).get(0)) })
+}
+
+
+pub(crate) fn set_config(conn: &Connection, name: &str, value: Json) -> Result<()> {
+    conn.execute("INSERT INTO config (name, value) VALUES ($1, $2)
+                  ON CONFLICT (name) DO UPDATE SET value = $2",
+                 &[&name, &value])?;
+    Ok(())
+}
@@ -0,0 +1,449 @@
+//! Runs of `build world`, building every release of the index matching a filter.
+//!
+//! The releases of a run are recorded in the `build_world_releases` table when it starts, and
+//! marked as they are built. An interrupted run is resumed from the first release which isn't
+//! marked, and its progress can be followed from another process.
+
+use super::crates::{crates_from_path, latest_version, older_versions};
+use error::Result;
+use postgres::Connection;
+use rustc_serialize::json::{Json, ToJson};
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+use std::process::Command;
+use time;
+use DocBuilderOptions;
+
+
+/// Selects the releases built by a run. Every set filter must match.
+#[derive(Default)]
+pub struct WorldFilter {
+    /// Crates whose name matches this glob, like `tokio-*`
+    pub name_glob: Option<String>,
+    /// Only these crates
+    pub crates: Option<Vec<String>>,
+    /// Releases published in the index after this date
+    pub published_after: Option<time::Timespec>,
+    /// Builds at most this number of releases
+    pub limit: Option<usize>,
+}
+
+impl WorldFilter {
+    /// Returns a short description of the filters, stored with the run
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(ref glob) = self.name_glob {
+            parts.push(format!("crates matching {}", glob));
+        }
+        if let Some(ref crates) = self.crates {
+            parts.push(format!("{} listed crates", crates.len()));
+        }
+        if let Some(date) = self.published_after {
+            parts.push(format!("published after {}",
+                               time::at_utc(date).strftime("%Y-%m-%d").unwrap()));
+        }
+        if let Some(limit) = self.limit {
+            parts.push(format!("at most {} releases", limit));
+        }
+
+        if parts.is_empty() {
+            "all releases".to_owned()
+        } else {
+            parts.join(", ")
+        }
+    }
+
+    /// Returns the filters and the options selecting the releases. It's stored with the run, so a
+    /// run is only resumed with the same selection.
+    pub fn selection(&self, options: &DocBuilderOptions) -> Json {
+        let mut m: BTreeMap<String, Json> = BTreeMap::new();
+        m.insert("name_glob".to_string(), self.name_glob.to_json());
+        m.insert("crates".to_string(), self.crates.to_json());
+        m.insert("published_after".to_string(),
+                 self.published_after.map(|date| date.sec).to_json());
+        m.insert("limit".to_string(), self.limit.to_json());
+        m.insert("only_latest_version".to_string(), options.build_only_latest_version.to_json());
+        m.insert("skip_oldest_versions".to_string(), options.skip_oldest_versions.to_json());
+        m.to_json()
+    }
+
+    /// Returns the releases of the index matching the filters, in build order: the latest
+    /// version of every crate first, then the older ones unless `build_only_latest_version` is
+    /// set.
+    pub fn select_releases(&self, options: &DocBuilderOptions) -> Result<Vec<(String, String)>> {
+        let published = match self.published_after {
+            Some(date) => versions_published_after(&options.crates_io_index_path, date)?,
+            None => None,
+        };
+        let matches = |name: &str, version: &str| {
+            published.as_ref().map_or(true, |published| {
+                published.contains(&(name.to_owned(), version.to_owned()))
+            })
+        };
+
+        let mut crates = Vec::new();
+        crates_from_path(&options.crates_io_index_path, &mut |name, versions| {
+            let glob_matches = self.name_glob.as_ref()
+                .map_or(true, |glob| glob_matches(glob, name));
+            let listed = self.crates.as_ref()
+                .map_or(true, |crates| crates.iter().any(|c| c == name));
+            if glob_matches && listed {
+                crates.push((name.to_owned(), versions));
+            }
+        })?;
+        // the index is walked in directory order
+        crates.sort();
+
+        let mut releases = Vec::new();
+        for &(ref name, ref versions) in &crates {
+            if let Some(version) = latest_version(versions) {
+                if matches(name, version) {
+                    releases.push((name.clone(), version.to_owned()));
+                }
+            }
+        }
+        if !options.build_only_latest_version {
+            for &(ref name, ref versions) in &crates {
+                for version in older_versions(versions, options.skip_oldest_versions) {
+                    if matches(name, &version) {
+                        releases.push((name.clone(), version));
+                    }
+                }
+            }
+        }
+
+        if let Some(limit) = self.limit {
+            releases.truncate(limit);
+        }
+        Ok(releases)
+    }
+}
+
+
+/// Outcome of the build of a release in a run
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum WorldStatus {
+    Built,
+    Failed,
+    /// The release was already built, see `DocBuilder::should_build`
+    Skipped,
+}
+
+impl WorldStatus {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            WorldStatus::Built => "built",
+            WorldStatus::Failed => "failed",
+            WorldStatus::Skipped => "skipped",
+        }
+    }
+}
+
+
+/// Progress of a `build world` run
+pub struct WorldRun {
+    pub id: i32,
+    pub description: String,
+    pub started_at: time::Timespec,
+    pub finished_at: Option<time::Timespec>,
+    pub total: i64,
+    pub built: i64,
+    pub failed: i64,
+    pub skipped: i64,
+    /// Average duration of the builds of the run, in milliseconds
+    pub average_duration_ms: Option<f64>,
+}
+
+impl WorldRun {
+    pub fn remaining(&self) -> i64 {
+        self.total - self.built - self.failed - self.skipped
+    }
+
+    /// Returns the estimated time until the run is finished, based on the average duration of
+    /// its builds
+    pub fn eta(&self) -> Option<time::Duration> {
+        if self.finished_at.is_some() {
+            return None;
+        }
+        self.average_duration_ms.map(|average| {
+            time::Duration::milliseconds((average * self.remaining() as f64) as i64)
+        })
+    }
+}
+
+impl ToJson for WorldRun {
+    fn to_json(&self) -> Json {
+        let mut m: BTreeMap<String, Json> = BTreeMap::new();
+        m.insert("id".to_string(), self.id.to_json());
+        m.insert("description".to_string(), self.description.to_json());
+        m.insert("started_at".to_string(),
+                 format!("{}", time::at(self.started_at).rfc3339()).to_json());
+        m.insert("finished_at".to_string(),
+                 self.finished_at.map(|t| format!("{}", time::at(t).rfc3339())).to_json());
+        m.insert("total".to_string(), self.total.to_json());
+        m.insert("built".to_string(), self.built.to_json());
+        m.insert("failed".to_string(), self.failed.to_json());
+        m.insert("skipped".to_string(), self.skipped.to_json());
+        m.insert("remaining".to_string(), self.remaining().to_json());
+        m.insert("eta_seconds".to_string(), self.eta().map(|eta| eta.num_seconds()).to_json());
+        m.to_json()
+    }
+}
+
+
+/// Records a new run building `releases` in this order, and returns its id
+pub(crate) fn start_world_run(conn: &Connection,
+                              description: &str,
+                              selection: &Json,
+                              releases: &[(String, String)])
+                              -> Result<i32> {
+    let names: Vec<&str> = releases.iter().map(|&(ref name, _)| name.as_str()).collect();
+    let versions: Vec<&str> = releases.iter().map(|&(_, ref version)| version.as_str()).collect();
+
+    let trans = conn.transaction()?;
+    let id: i32 = trans.query("INSERT INTO build_world_runs (description, selection)
+                               VALUES ($1, $2)
+                               RETURNING id",
+                              &[&description, selection])?
+        .get(0)
+        .get(0);
+    trans.execute("INSERT INTO build_world_releases (run_id, position, name, version)
+                   SELECT $1, release.position::INT, release.name, release.version
+                   FROM unnest($2::VARCHAR[], $3::VARCHAR[])
+                        WITH ORDINALITY AS release(name, version, position)",
+                  &[&id, &names, &versions])?;
+    trans.commit()?;
+    Ok(id)
+}
+
+
+/// Returns the id, the description and the selection of the last run, if it isn't finished
+pub(crate) fn unfinished_world_run(conn: &Connection) -> Result<Option<(i32, String, Json)>> {
+    let rows = conn.query("SELECT id, description, selection FROM build_world_runs
+                           WHERE finished_at IS NULL
+                           ORDER BY id DESC
+                           LIMIT 1",
+                          &[])?;
+    Ok(rows.iter().next().map(|row| (row.get(0), row.get(1), row.get(2))))
+}
+
+
+/// Returns the position, name and version of the next release to build in a run
+pub(crate) fn next_world_release(conn: &Connection,
+                                 run_id: i32)
+                                 -> Result<Option<(i32, String, String)>> {
+    let rows = conn.query("SELECT position, name, version
+                           FROM build_world_releases
+                           WHERE run_id = $1 AND status IS NULL
+                           ORDER BY position
+                           LIMIT 1",
+                          &[&run_id])?;
+    Ok(rows.iter().next().map(|row| (row.get(0), row.get(1), row.get(2))))
+}
+
+
+pub(crate) fn record_world_release(conn: &Connection,
+                                   run_id: i32,
+                                   position: i32,
+                                   status: WorldStatus,
+                                   duration_ms: i64,
+                                   error: Option<String>)
+                                   -> Result<()> {
+    conn.execute("UPDATE build_world_releases
+                  SET status = $3, duration_ms = $4, error = $5
+                  WHERE run_id = $1 AND position = $2",
+                 &[&run_id, &position, &status.as_str(), &duration_ms, &error])?;
+    Ok(())
+}
+
+
+pub(crate) fn finish_world_run(conn: &Connection, run_id: i32) -> Result<()> {
+    conn.execute("UPDATE build_world_runs SET finished_at = NOW() WHERE id = $1",
+                 &[&run_id])?;
+    Ok(())
+}
+
+
+/// Returns the progress of the last run
+pub fn get_world_run(conn: &Connection) -> Result<Option<WorldRun>> {
+    let rows = conn.query("SELECT build_world_runs.id,
+                                  build_world_runs.description,
+                                  build_world_runs.started_at,
+                                  build_world_runs.finished_at,
+                                  COUNT(build_world_releases.position),
+                                  COUNT(*) FILTER (WHERE status = 'built'),
+                                  COUNT(*) FILTER (WHERE status = 'failed'),
+                                  COUNT(*) FILTER (WHERE status = 'skipped'),
+                                  AVG(duration_ms) FILTER (WHERE status != 'skipped')::FLOAT8
+                           FROM build_world_runs
+                           LEFT JOIN build_world_releases
+                                  ON build_world_releases.run_id = build_world_runs.id
+                           GROUP BY build_world_runs.id
+                           ORDER BY build_world_runs.id DESC
+                           LIMIT 1",
+                          &[])?;
+
+    Ok(rows.iter().next().map(|row| {
+        WorldRun {
+            id: row.get(0),
+            description: row.get(1),
+            started_at: row.get(2),
+            finished_at: row.get(3),
+            total: row.get(4),
+            built: row.get(5),
+            failed: row.get(6),
+            skipped: row.get(7),
+            average_duration_ms: row.get(8),
+        }
+    }))
+}
+
+
+/// Returns the releases added to the index after `date`, from the history of its git repository.
+/// Returns `None` if the index has no commit before `date`, since every release is newer.
+fn versions_published_after(index_path: &Path,
+                            date: time::Timespec)
+                            -> Result<Option<HashSet<(String, String)>>> {
+    let date = time::at_utc(date).rfc3339().to_string();
+    let output = Command::new("git")
+        .arg("-C").arg(index_path)
+        .args(&["rev-list", "-1", &format!("--before={}", date), "HEAD"])
+        .output()?;
+    if !output.status.success() {
+        bail!("git rev-list failed: {}", String::from_utf8_lossy(&output.stderr));
+    }
+    let since = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    if since.is_empty() {
+        return Ok(None);
+    }
+
+    let output = Command::new("git")
+        .arg("-C").arg(index_path)
+        .args(&["diff", "--unified=0", &since, "HEAD"])
+        .output()?;
+    if !output.status.success() {
+        bail!("git diff failed: {}", String::from_utf8_lossy(&output.stderr));
+    }
+
+    Ok(Some(added_releases(&String::from_utf8_lossy(&output.stdout))))
+}
+
+
+/// Returns the releases added by a diff of the index. Yanks and unyanks rewrite the line of a
+/// release, which is removed and added again: the releases with a removed line aren't new.
+fn added_releases(diff: &str) -> HashSet<(String, String)> {
+    let releases = |prefix: &'static str| {
+        diff.lines()
+            .filter(move |line| line.starts_with(prefix))
+            .filter_map(|line| Json::from_str(&line[1..]).ok())
+            .filter_map(|release| {
+                let name = release.find("name").and_then(|n| n.as_string())?.to_owned();
+                let version = release.find("vers").and_then(|v| v.as_string())?.to_owned();
+                Some((name, version))
+            })
+            .collect::<HashSet<(String, String)>>()
+    };
+    let removed = releases("-{");
+    releases("+{").into_iter().filter(|release| !removed.contains(release)).collect()
+}
+
+
+/// Matches `name` against a glob, where `*` matches any sequence of characters and `?` a single
+/// character
+fn glob_matches(glob: &str, name: &str) -> bool {
+    fn matches(glob: &[char], name: &[char]) -> bool {
+        match glob.first() {
+            None => name.is_empty(),
+            Some(&'*') => (0..name.len() + 1).any(|i| matches(&glob[1..], &name[i..])),
+            Some(&'?') => !name.is_empty() && matches(&glob[1..], &name[1..]),
+            Some(c) => name.first() == Some(c) && matches(&glob[1..], &name[1..]),
+        }
+    }
+
+    let glob: Vec<char> = glob.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    matches(&glob, &name)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{added_releases, glob_matches, WorldFilter, WorldRun};
+    use time;
+    use DocBuilderOptions;
+
+    #[test]
+    fn test_added_releases() {
+        let diff = r#"diff --git a/se/rd/serde b/se/rd/serde
+--- a/se/rd/serde
++++ b/se/rd/serde
+@@ -12 +12 @@
+-{"name":"serde","vers":"1.0.0","deps":[],"cksum":"","features":{},"yanked":false}
++{"name":"serde","vers":"1.0.0","deps":[],"cksum":"","features":{},"yanked":true}
+@@ -20,0 +21 @@
++{"name":"serde","vers":"1.0.1","deps":[],"cksum":"","features":{},"yanked":false}
+"#;
+        let added = added_releases(diff);
+        assert_eq!(added.len(), 1);
+        assert!(added.contains(&("serde".to_owned(), "1.0.1".to_owned())));
+    }
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(glob_matches("tokio-*", "tokio-io"));
+        assert!(glob_matches("*-sys", "openssl-sys"));
+        assert!(glob_matches("ra?d", "rand"));
+        assert!(glob_matches("*", "serde"));
+        assert!(!glob_matches("tokio-*", "tokio"));
+        assert!(!glob_matches("ra?d", "rnd"));
+    }
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(WorldFilter::default().describe(), "all releases");
+
+        let filter = WorldFilter {
+            name_glob: Some("tokio-*".to_owned()),
+            limit: Some(100),
+            ..Default::default()
+        };
+        assert_eq!(filter.describe(), "crates matching tokio-*, at most 100 releases");
+    }
+
+    #[test]
+    fn test_selection() {
+        let options = DocBuilderOptions::default();
+        let filter = WorldFilter {
+            crates: Some(vec!["rand".to_owned(), "serde".to_owned()]),
+            ..Default::default()
+        };
+        let other = WorldFilter {
+            crates: Some(vec!["rand".to_owned(), "tokio".to_owned()]),
+            ..Default::default()
+        };
+        // both are described as "2 listed crates"
+        assert_eq!(filter.describe(), other.describe());
+        assert!(filter.selection(&options) != other.selection(&options));
+        assert_eq!(filter.selection(&options), filter.selection(&options));
+    }
+
+    #[test]
+    fn test_eta() {
+        let mut run = WorldRun {
+            id: 1,
+            description: "all releases".to_owned(),
+            started_at: time::get_time(),
+            finished_at: None,
+            total: 100,
+            built: 30,
+            failed: 5,
+            skipped: 15,
+            average_duration_ms: Some(2000.0),
+        };
+        assert_eq!(run.remaining(), 50);
+        assert_eq!(run.eta(), Some(time::Duration::seconds(100)));
+
+        run.finished_at = Some(time::get_time());
+        assert_eq!(run.eta(), None);
+    }
+}
@@ -28,6 +28,9 @@ extern crate r2d2_postgres;
 extern crate url;
 extern crate params;
 extern crate libc;
+extern crate flate2;
+extern crate tar;
+extern crate sha2;
 extern crate badge;
 extern crate crates_index_diff;
 extern crate toml;
@@ -47,11 +50,14 @@ pub use self::docbuilder::RustwideBuilder;
 pub use self::docbuilder::DocBuilder;
 pub use self::docbuilder::options::DocBuilderOptions;
 pub use self::docbuilder::metadata::Metadata;
-pub use self::web::start_web_server;
+pub use self::docbuilder::overrides::BuildOverrides;
+pub use self::docbuilder::world::{get_world_run, WorldFilter};
+pub use self::web::{start_preview_server, start_web_server};
 
 pub mod error;
 pub mod db;
 pub mod utils;
+pub mod storage;
 mod docbuilder;
 mod web;
 
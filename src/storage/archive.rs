@@ -0,0 +1,203 @@
+//! Storage layer packing the files of a release into a single archive.
+//!
+//! Instead of storing thousands of small objects per release, `store_archive` writes them into
+//! one uncompressed tar file and records where every file is located in the `archive_index`
+//! table. Files are then read back with ranged reads into the archive. Each file keeps its own
+//! compression, so ranged reads return content that can be served as is.
+
+use super::{Blob, Storage};
+use std::sync::Arc;
+use error::Result;
+use r2d2;
+use r2d2_postgres::PostgresConnectionManager;
+use tar;
+use time;
+
+
+const ARCHIVE_MIME: &'static str = "application/x-tar";
+
+
+/// Location of a file inside an archive
+#[derive(Debug, PartialEq)]
+struct IndexEntry {
+    path: String,
+    offset: u64,
+    length: u64,
+}
+
+
+pub struct ArchiveStorage {
+    inner: Arc<dyn Storage>,
+    pool: r2d2::Pool<PostgresConnectionManager>,
+}
+
+impl ArchiveStorage {
+    pub fn new(inner: Arc<dyn Storage>,
+               pool: r2d2::Pool<PostgresConnectionManager>)
+               -> ArchiveStorage {
+        ArchiveStorage { inner, pool }
+    }
+}
+
+impl Storage for ArchiveStorage {
+    fn get(&self, path: &str) -> Result<Option<Blob>> {
+        let conn = self.pool.get()?;
+        let rows = conn.query("SELECT archive, file_offset, file_length, mime, compression,
+                                      date_updated
+                               FROM archive_index
+                               WHERE path = $1", &[&path])?;
+
+        if rows.is_empty() {
+            return self.inner.get(path);
+        }
+
+        let row = rows.get(0);
+        let archive: String = row.get(0);
+        let offset: i64 = row.get(1);
+        let length: i64 = row.get(2);
+        match self.inner.get_range(&archive, offset as u64, length as u64)? {
+            Some(content) => Ok(Some(Blob {
+                path: path.into(),
+                mime: row.get(3),
+                date_updated: row.get(5),
+                content: content,
+                compression: row.get(4),
+            })),
+            None => {
+                warn!("{} is indexed but archive {} is missing", path, archive);
+                Ok(None)
+            }
+        }
+    }
+
+    fn get_range(&self, path: &str, start: u64, length: u64) -> Result<Option<Vec<u8>>> {
+        self.inner.get_range(path, start, length)
+    }
+
+    fn store(&self, blobs: &[Blob]) -> Result<()> {
+        self.inner.store(blobs)
+    }
+
+    fn store_archive(&self, archive: &str, blobs: &[Blob]) -> Result<()> {
+        let (content, index) = build_archive(blobs)?;
+        self.inner.store(&[Blob {
+            path: archive.into(),
+            mime: ARCHIVE_MIME.into(),
+            date_updated: time::get_time(),
+            content: content,
+            compression: None,
+        }])?;
+
+        // Replace the whole index of the archive at once, so readers either see the old or the
+        // new release
+        let conn = self.pool.get()?;
+        let trans = conn.transaction()?;
+        trans.execute("DELETE FROM archive_index WHERE archive = $1", &[&archive])?;
+        for (entry, blob) in index.iter().zip(blobs) {
+            trans.execute("INSERT INTO archive_index (path, archive, file_offset, file_length,
+                                                      mime, compression)
+                           VALUES ($1, $2, $3, $4, $5, $6)
+                           ON CONFLICT (path) DO UPDATE
+                               SET archive = EXCLUDED.archive,
+                                   file_offset = EXCLUDED.file_offset,
+                                   file_length = EXCLUDED.file_length,
+                                   mime = EXCLUDED.mime,
+                                   compression = EXCLUDED.compression,
+                                   date_updated = NOW()",
+                          &[&entry.path,
+                            &archive,
+                            &(entry.offset as i64),
+                            &(entry.length as i64),
+                            &blob.mime,
+                            &blob.compression])?;
+        }
+        trans.commit()?;
+        Ok(())
+    }
+
+    fn supports_archives(&self) -> bool {
+        true
+    }
+
+    fn delete_prefix(&self, prefix: &str) -> Result<()> {
+        let conn = self.pool.get()?;
+        let archives: Vec<String> = conn.query("SELECT DISTINCT archive
+                                                FROM archive_index
+                                                WHERE left(path, length($1)) = $1",
+                                               &[&prefix])?
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+
+        for archive in &archives {
+            self.inner.delete_prefix(archive)?;
+            conn.execute("DELETE FROM archive_index WHERE archive = $1", &[archive])?;
+        }
+
+        // files stored before archives were enabled
+        self.inner.delete_prefix(prefix)
+    }
+}
+
+
+/// Packs the blobs into a tar archive and returns it with the location of every blob, in the
+/// same order as `blobs`.
+fn build_archive(blobs: &[Blob]) -> Result<(Vec<u8>, Vec<IndexEntry>)> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for blob in blobs {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(blob.content.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(time::get_time().sec as u64);
+        builder.append_data(&mut header, &blob.path, &blob.content[..])?;
+    }
+    let content = builder.into_inner()?;
+
+    // Long paths are stored in extra headers, read the archive back to get the real offsets
+    let mut index = Vec::with_capacity(blobs.len());
+    let mut archive = tar::Archive::new(&content[..]);
+    for entry in archive.entries()? {
+        let entry = entry?;
+        index.push(IndexEntry {
+            path: entry.path()?.to_string_lossy().into_owned(),
+            offset: entry.raw_file_position(),
+            length: entry.header().size()?,
+        });
+    }
+
+    Ok((content, index))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::build_archive;
+    use storage::Blob;
+    use time;
+
+    #[test]
+    fn test_build_archive() {
+        let long_path = format!("rustdoc/foo/0.1.0/{}/index.html", "a".repeat(120));
+        let blobs: Vec<Blob> = vec![("rustdoc/foo/0.1.0/foo/index.html", "<html></html>"),
+                                    (&long_path[..], "long"),
+                                    ("rustdoc/foo/0.1.0/main.js", "")]
+            .into_iter()
+            .map(|(path, content)| Blob {
+                path: path.to_owned(),
+                mime: "text/html".into(),
+                date_updated: time::get_time(),
+                content: content.as_bytes().to_vec(),
+                compression: None,
+            })
+            .collect();
+
+        let (archive, index) = build_archive(&blobs).unwrap();
+        assert_eq!(index.len(), blobs.len());
+        for (entry, blob) in index.iter().zip(&blobs) {
+            assert_eq!(entry.path, blob.path);
+            let start = entry.offset as usize;
+            let end = start + entry.length as usize;
+            assert_eq!(&archive[start..end], &blob.content[..]);
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//! Transparent compression of stored files.
+//!
+//! Text files (rustdoc HTML, sources, scripts) are gzipped before being stored. The algorithm is
+//! recorded next to the file by every backend, so old uncompressed files keep working.
+
+use std::io::{Read, Write};
+use error::Result;
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+
+
+/// Name of the gzip algorithm, as recorded in storage and used in `Content-Encoding`
+pub const GZIP: &'static str = "gzip";
+
+
+/// Returns `true` if files with this mime type are worth compressing
+pub fn is_compressible(mime: &str) -> bool {
+    mime.starts_with("text/") || mime == "application/javascript" ||
+    mime == "application/json" || mime == "image/svg+xml"
+}
+
+
+/// Compresses `content` with gzip
+pub fn compress(content: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(content)?;
+    Ok(encoder.finish()?)
+}
+
+
+/// Decompresses `content` compressed with `algorithm`
+pub fn decompress(algorithm: &str, content: &[u8]) -> Result<Vec<u8>> {
+    match algorithm {
+        GZIP => {
+            let mut decompressed = Vec::new();
+            GzDecoder::new(content).read_to_end(&mut decompressed)?;
+            Ok(decompressed)
+        }
+        other => bail!("unknown compression algorithm '{}'", other),
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_compression() {
+        let content = "<html><body>hello</body></html>".repeat(100);
+        let compressed = compress(content.as_bytes()).unwrap();
+        assert!(compressed.len() < content.len());
+        assert_eq!(decompress(GZIP, &compressed).unwrap(), content.as_bytes().to_vec());
+        assert!(decompress("zstd", &compressed).is_err());
+
+        assert!(is_compressible("text/html"));
+        assert!(is_compressible("application/javascript"));
+        assert!(!is_compressible("image/png"));
+    }
+}
@@ -0,0 +1,86 @@
+//! Storage backend keeping files in the `files` table.
+//!
+//! cratesfyi is generating more than 5 million files, they are small and mostly html files.
+//! They are using so many inodes and it is better to store them in database instead of
+//! filesystem.
+
+use super::{Blob, Storage};
+use error::Result;
+use r2d2;
+use r2d2_postgres::PostgresConnectionManager;
+
+
+pub struct DatabaseStorage {
+    pool: r2d2::Pool<PostgresConnectionManager>,
+}
+
+impl DatabaseStorage {
+    pub fn new(pool: r2d2::Pool<PostgresConnectionManager>) -> DatabaseStorage {
+        DatabaseStorage { pool }
+    }
+}
+
+impl Storage for DatabaseStorage {
+    fn get(&self, path: &str) -> Result<Option<Blob>> {
+        let conn = self.pool.get()?;
+        let rows = conn.query("SELECT path, mime, date_updated, content, compression
+                               FROM files
+                               WHERE path = $1", &[&path])?;
+
+        if rows.is_empty() {
+            Ok(None)
+        } else {
+            let row = rows.get(0);
+            Ok(Some(Blob {
+                path: row.get(0),
+                mime: row.get(1),
+                date_updated: row.get(2),
+                content: row.get(3),
+                compression: row.get(4),
+            }))
+        }
+    }
+
+    fn get_range(&self, path: &str, start: u64, length: u64) -> Result<Option<Vec<u8>>> {
+        // substring only takes 32-bit offsets for bytea, which can't be larger than 1 GiB anyway
+        if start.checked_add(length).map_or(true, |end| end >= i32::max_value() as u64) {
+            bail!("range {}+{} of {} is out of bounds", start, length, path);
+        }
+
+        let conn = self.pool.get()?;
+        // substring is 1-indexed
+        let rows = conn.query("SELECT substring(content FROM $2 FOR $3)
+                               FROM files
+                               WHERE path = $1",
+                              &[&path, &(start as i32 + 1), &(length as i32)])?;
+
+        if rows.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(rows.get(0).get(0)))
+        }
+    }
+
+    fn store(&self, blobs: &[Blob]) -> Result<()> {
+        let conn = self.pool.get()?;
+        let trans = conn.transaction()?;
+        for blob in blobs {
+            trans.execute("INSERT INTO files (path, mime, content, compression)
+                           VALUES ($1, $2, $3, $4)
+                           ON CONFLICT (path) DO UPDATE
+                               SET mime = EXCLUDED.mime,
+                                   content = EXCLUDED.content,
+                                   compression = EXCLUDED.compression,
+                                   date_updated = NOW()",
+                          &[&blob.path, &blob.mime, &blob.content, &blob.compression])?;
+        }
+        trans.commit()?;
+        Ok(())
+    }
+
+    fn delete_prefix(&self, prefix: &str) -> Result<()> {
+        let conn = self.pool.get()?;
+        conn.execute("DELETE FROM files WHERE left(path, length($1)) = $1", &[&prefix])?;
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+//! Storage layer deduplicating identical files.
+//!
+//! File contents are stored once under `blobs/{sha256}`, and the `file_hashes` table maps every
+//! path to the hash of its content. Files whose hash is already stored are not uploaded again,
+//! which saves a lot of space for sources and scripts repeated across releases.
+//!
+//! The `blobs` table lists the stored hashes. Its rows are locked while paths are added to or
+//! removed from a blob, so a blob is never deleted while a new path starts pointing to it.
+
+use super::{Blob, Storage};
+use std::sync::Arc;
+use error::Result;
+use r2d2;
+use r2d2_postgres::PostgresConnectionManager;
+use sha2::{Digest, Sha256};
+
+
+pub struct DedupStorage {
+    inner: Arc<dyn Storage>,
+    pool: r2d2::Pool<PostgresConnectionManager>,
+}
+
+impl DedupStorage {
+    pub fn new(inner: Arc<dyn Storage>,
+               pool: r2d2::Pool<PostgresConnectionManager>)
+               -> DedupStorage {
+        DedupStorage { inner, pool }
+    }
+
+    /// Returns the hash of the content stored at `path`, if the path is deduplicated
+    fn hash_of(&self, path: &str) -> Result<Option<String>> {
+        let conn = self.pool.get()?;
+        let rows = conn.query("SELECT hash FROM file_hashes WHERE path = $1", &[&path])?;
+        Ok(if rows.is_empty() { None } else { Some(rows.get(0).get(0)) })
+    }
+}
+
+impl Storage for DedupStorage {
+    fn get(&self, path: &str) -> Result<Option<Blob>> {
+        let conn = self.pool.get()?;
+        let rows = conn.query("SELECT hash, mime, compression, date_updated
+                               FROM file_hashes
+                               WHERE path = $1", &[&path])?;
+
+        if rows.is_empty() {
+            return self.inner.get(path);
+        }
+
+        let row = rows.get(0);
+        let hash: String = row.get(0);
+        match self.inner.get(&blob_path(&hash))? {
+            Some(blob) => Ok(Some(Blob {
+                path: path.into(),
+                mime: row.get(1),
+                date_updated: row.get(3),
+                content: blob.content,
+                compression: row.get(2),
+            })),
+            None => {
+                warn!("{} is mapped to missing blob {}", path, hash);
+                Ok(None)
+            }
+        }
+    }
+
+    fn get_range(&self, path: &str, start: u64, length: u64) -> Result<Option<Vec<u8>>> {
+        match self.hash_of(path)? {
+            Some(hash) => self.inner.get_range(&blob_path(&hash), start, length),
+            None => self.inner.get_range(path, start, length),
+        }
+    }
+
+    fn store(&self, blobs: &[Blob]) -> Result<()> {
+        let hashes: Vec<String> = blobs.iter().map(|blob| hash(&blob.content)).collect();
+
+        let conn = self.pool.get()?;
+        let trans = conn.transaction()?;
+        // Locks the stored blobs until the new paths point to them, so a concurrent
+        // `delete_prefix` can't remove them in the meantime
+        let existing: Vec<String> = trans.query("SELECT hash
+                                                 FROM blobs
+                                                 WHERE hash = ANY($1)
+                                                 ORDER BY hash
+                                                 FOR UPDATE",
+                                                &[&hashes])?
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+
+        // Upload every new content once, even if several paths share it
+        let mut new_blobs: Vec<Blob> = Vec::new();
+        for (blob, hash) in blobs.iter().zip(&hashes) {
+            let path = blob_path(hash);
+            if existing.contains(hash) || new_blobs.iter().any(|b| b.path == path) {
+                continue;
+            }
+            new_blobs.push(Blob {
+                path: path,
+                mime: blob.mime.clone(),
+                date_updated: blob.date_updated,
+                content: blob.content.clone(),
+                compression: blob.compression.clone(),
+            });
+        }
+        debug!("storing {} new blobs out of {} files", new_blobs.len(), blobs.len());
+        self.inner.store(&new_blobs)?;
+
+        for hash in hashes.iter().filter(|hash| !existing.contains(hash)) {
+            trans.execute("INSERT INTO blobs (hash) VALUES ($1) ON CONFLICT DO NOTHING",
+                          &[hash])?;
+        }
+        for (blob, hash) in blobs.iter().zip(&hashes) {
+            trans.execute("INSERT INTO file_hashes (path, hash, mime, compression)
+                           VALUES ($1, $2, $3, $4)
+                           ON CONFLICT (path) DO UPDATE
+                               SET hash = EXCLUDED.hash,
+                                   mime = EXCLUDED.mime,
+                                   compression = EXCLUDED.compression,
+                                   date_updated = NOW()",
+                          &[&blob.path, hash, &blob.mime, &blob.compression])?;
+        }
+        trans.commit()?;
+        Ok(())
+    }
+
+    fn store_archive(&self, archive: &str, blobs: &[Blob]) -> Result<()> {
+        self.inner.store_archive(archive, blobs)
+    }
+
+    fn supports_archives(&self) -> bool {
+        self.inner.supports_archives()
+    }
+
+    fn delete_prefix(&self, prefix: &str) -> Result<()> {
+        let conn = self.pool.get()?;
+        let trans = conn.transaction()?;
+        let hashes: Vec<String> = trans.query("DELETE FROM file_hashes
+                                               WHERE left(path, length($1)) = $1
+                                               RETURNING hash",
+                                              &[&prefix])?
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+
+        // Blobs can be shared with other paths, only delete the ones nothing points to anymore.
+        // The lock waits for a concurrent `store` adding paths to them to commit.
+        trans.execute("SELECT hash FROM blobs WHERE hash = ANY($1) ORDER BY hash FOR UPDATE",
+                      &[&hashes])?;
+        let unused = trans.query("DELETE FROM blobs
+                                  WHERE hash = ANY($1)
+                                    AND NOT EXISTS (SELECT 1 FROM file_hashes
+                                                    WHERE file_hashes.hash = blobs.hash)
+                                  RETURNING hash",
+                                 &[&hashes])?;
+        for row in &unused {
+            let hash: String = row.get(0);
+            self.inner.delete_prefix(&blob_path(&hash))?;
+        }
+        trans.commit()?;
+
+        // files stored before deduplication was enabled
+        self.inner.delete_prefix(prefix)
+    }
+}
+
+
+/// Returns the hex encoded SHA-256 hash of `content`
+fn hash(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+
+fn blob_path(hash: &str) -> String {
+    format!("blobs/{}", hash)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{blob_path, hash};
+
+    #[test]
+    fn test_hash() {
+        assert_eq!(hash(b""),
+                   "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(hash(b"fn main() {}"), hash(b"fn main() {}"));
+        assert!(hash(b"fn main() {}") != hash(b"fn main() { }"));
+        assert_eq!(blob_path(&hash(b"")),
+                   "blobs/e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    }
+}
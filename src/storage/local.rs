@@ -0,0 +1,255 @@
+//! Storage backend keeping files in a local directory.
+//!
+//! File contents are stored under `files/` and their mime types under `meta/`, both mirroring
+//! the path of the stored file. The meta file contains the mime type on its first line and the
+//! compression algorithm, if any, on the second one.
+
+use super::{Blob, Storage};
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use error::Result;
+use time;
+
+
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new<P: AsRef<Path>>(root: P) -> LocalStorage {
+        LocalStorage { root: root.as_ref().to_path_buf() }
+    }
+
+    fn file_path(&self, kind: &str, path: &str) -> Option<PathBuf> {
+        // never allow paths to escape the storage directory
+        if path.is_empty() || path.split('/').any(|segment| segment == "..") {
+            return None;
+        }
+        Some(self.root.join(kind).join(path.trim_start_matches('/')))
+    }
+}
+
+impl Storage for LocalStorage {
+    fn get(&self, path: &str) -> Result<Option<Blob>> {
+        let (file_path, meta_path) = match (self.file_path("files", path),
+                                            self.file_path("meta", path)) {
+            (Some(file_path), Some(meta_path)) => (file_path, meta_path),
+            _ => return Ok(None),
+        };
+
+        let mut file = match fs::File::open(&file_path) {
+            Ok(f) => f,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        if !file.metadata()?.is_file() {
+            return Ok(None);
+        }
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
+
+        let mut meta = String::new();
+        fs::File::open(&meta_path)?.read_to_string(&mut meta)?;
+        let mut meta = meta.lines().map(|line| line.trim());
+        let mime = meta.next().unwrap_or_default().to_owned();
+        let compression = meta.next().filter(|c| !c.is_empty()).map(|c| c.to_owned());
+
+        let modified = file.metadata()?.modified()?.duration_since(UNIX_EPOCH)?;
+
+        Ok(Some(Blob {
+            path: path.into(),
+            mime: mime,
+            date_updated: time::Timespec::new(modified.as_secs() as i64,
+                                              modified.subsec_nanos() as i32),
+            content,
+            compression,
+        }))
+    }
+
+    fn get_range(&self, path: &str, start: u64, length: u64) -> Result<Option<Vec<u8>>> {
+        let file_path = match self.file_path("files", path) {
+            Some(file_path) => file_path,
+            None => return Ok(None),
+        };
+
+        let mut file = match fs::File::open(&file_path) {
+            Ok(f) => f,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        if !file.metadata()?.is_file() {
+            return Ok(None);
+        }
+        file.seek(SeekFrom::Start(start))?;
+        let mut content = Vec::new();
+        file.take(length).read_to_end(&mut content)?;
+        Ok(Some(content))
+    }
+
+    fn store(&self, blobs: &[Blob]) -> Result<()> {
+        for blob in blobs {
+            let (file_path, meta_path) = match (self.file_path("files", &blob.path),
+                                                self.file_path("meta", &blob.path)) {
+                (Some(file_path), Some(meta_path)) => (file_path, meta_path),
+                _ => bail!("invalid path '{}'", blob.path),
+            };
+
+            for path in &[&file_path, &meta_path] {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+            }
+
+            fs::File::create(&file_path)?.write_all(&blob.content)?;
+            let mut meta = fs::File::create(&meta_path)?;
+            writeln!(meta, "{}", blob.mime)?;
+            if let Some(ref compression) = blob.compression {
+                writeln!(meta, "{}", compression)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn delete_prefix(&self, prefix: &str) -> Result<()> {
+        // only the directory containing the prefix needs to be searched
+        let dir = match prefix.rfind('/') {
+            Some(pos) => &prefix[..pos],
+            None => "",
+        };
+
+        for kind in &["files", "meta"] {
+            let root = self.root.join(kind);
+            let dir_path = if dir.is_empty() {
+                root.clone()
+            } else {
+                match self.file_path(kind, dir) {
+                    Some(dir_path) => dir_path,
+                    None => bail!("invalid prefix '{}'", prefix),
+                }
+            };
+            if !dir_path.is_dir() {
+                continue;
+            }
+
+            for path in walk_files(&dir_path)? {
+                let relative = path.strip_prefix(&root)?.to_string_lossy().into_owned();
+                if relative.starts_with(prefix) {
+                    fs::remove_file(&path)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+
+/// Returns the paths of every file in `dir` and its subdirectories
+fn walk_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            files.extend(walk_files(&entry.path())?);
+        } else {
+            files.push(entry.path());
+        }
+    }
+    Ok(files)
+}
+
+
+#[cfg(test)]
+mod test {
+    extern crate env_logger;
+    use super::LocalStorage;
+    use storage::{Blob, Storage};
+    use tempdir::TempDir;
+    use time;
+
+    fn blob(path: &str, content: &[u8]) -> Blob {
+        Blob {
+            path: path.into(),
+            mime: "text/html".into(),
+            date_updated: time::get_time(),
+            content: content.to_vec(),
+            compression: None,
+        }
+    }
+
+    #[test]
+    fn test_store_and_get() {
+        let _ = env_logger::try_init();
+        let dir = TempDir::new("cratesfyi-storage").unwrap();
+        let storage = LocalStorage::new(dir.path());
+
+        storage.store(&[blob("rustdoc/foo/0.1.0/foo/index.html", b"<html></html>"),
+                        blob("main.js", b"function() {}")]).unwrap();
+
+        let file = storage.get("rustdoc/foo/0.1.0/foo/index.html").unwrap().unwrap();
+        assert_eq!(file.path, "rustdoc/foo/0.1.0/foo/index.html");
+        assert_eq!(file.mime, "text/html");
+        assert_eq!(file.content, b"<html></html>".to_vec());
+        assert!(storage.get("main.js").unwrap().is_some());
+
+        // storing a file again replaces it
+        storage.store(&[blob("main.js", b"updated")]).unwrap();
+        assert_eq!(storage.get("main.js").unwrap().unwrap().content, b"updated".to_vec());
+
+        // the compression algorithm is kept with the file
+        let mut compressed = blob("style.css", b"compressed");
+        compressed.compression = Some("gzip".into());
+        storage.store(&[compressed]).unwrap();
+        let file = storage.get("style.css").unwrap().unwrap();
+        assert_eq!(file.mime, "text/html");
+        assert_eq!(file.compression, Some("gzip".into()));
+        assert_eq!(storage.get("main.js").unwrap().unwrap().compression, None);
+    }
+
+    #[test]
+    fn test_get_range() {
+        let dir = TempDir::new("cratesfyi-storage").unwrap();
+        let storage = LocalStorage::new(dir.path());
+
+        storage.store(&[blob("archive.tar", b"0123456789")]).unwrap();
+        assert_eq!(storage.get_range("archive.tar", 2, 3).unwrap(), Some(b"234".to_vec()));
+        assert_eq!(storage.get_range("archive.tar", 8, 5).unwrap(), Some(b"89".to_vec()));
+        assert_eq!(storage.get_range("missing.tar", 0, 1).unwrap(), None);
+    }
+
+    #[test]
+    fn test_delete_prefix() {
+        let dir = TempDir::new("cratesfyi-storage").unwrap();
+        let storage = LocalStorage::new(dir.path());
+
+        storage.store(&[blob("rustdoc/foo/0.1.0/foo/index.html", b""),
+                        blob("rustdoc/foo/0.1.0/main.js", b""),
+                        blob("rustdoc/foo/0.1.0-beta/foo/index.html", b""),
+                        blob("rustdoc/foo-bar/0.1.0/foo_bar/index.html", b"")]).unwrap();
+
+        storage.delete_prefix("rustdoc/foo/0.1.0/").unwrap();
+        assert!(storage.get("rustdoc/foo/0.1.0/foo/index.html").unwrap().is_none());
+        assert!(storage.get("rustdoc/foo/0.1.0/main.js").unwrap().is_none());
+        assert!(storage.get("rustdoc/foo/0.1.0-beta/foo/index.html").unwrap().is_some());
+
+        storage.delete_prefix("rustdoc/foo/").unwrap();
+        assert!(storage.get("rustdoc/foo/0.1.0-beta/foo/index.html").unwrap().is_none());
+        assert!(storage.get("rustdoc/foo-bar/0.1.0/foo_bar/index.html").unwrap().is_some());
+
+        // missing directories are ignored
+        storage.delete_prefix("rustdoc/missing/").unwrap();
+    }
+
+    #[test]
+    fn test_missing_files() {
+        let dir = TempDir::new("cratesfyi-storage").unwrap();
+        let storage = LocalStorage::new(dir.path());
+
+        assert!(storage.get("rustdoc/foo/0.1.0/foo/index.html").unwrap().is_none());
+        // directories and paths outside of the storage are never returned
+        storage.store(&[blob("rustdoc/foo/0.1.0/foo/index.html", b"")]).unwrap();
+        assert!(storage.get("rustdoc/foo").unwrap().is_none());
+        assert!(storage.get("../foo").unwrap().is_none());
+    }
+}
@@ -0,0 +1,147 @@
+//! Pluggable storage for rustdoc output, crate sources and other static files.
+//!
+//! Every file generated by the builder goes through a `Storage` implementation. The backend is
+//! chosen once at startup with `from_env`, and the web handlers and the builder only talk to the
+//! trait, without knowing where files actually end up.
+
+mod archive;
+mod compression;
+mod database;
+mod dedup;
+mod local;
+mod s3;
+
+pub use self::compression::{compress, decompress, is_compressible, GZIP};
+
+pub use self::archive::ArchiveStorage;
+pub use self::database::DatabaseStorage;
+pub use self::dedup::DedupStorage;
+pub use self::local::LocalStorage;
+pub use self::s3::S3Storage;
+
+use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
+use db::create_pool;
+use error::Result;
+use time;
+
+
+/// A file stored in a `Storage` backend.
+pub struct Blob {
+    pub path: String,
+    pub mime: String,
+    pub date_updated: time::Timespec,
+    pub content: Vec<u8>,
+    /// Algorithm `content` is compressed with, if any
+    pub compression: Option<String>,
+}
+
+impl Blob {
+    /// Returns the blob with its content decompressed.
+    pub fn decompress(mut self) -> Result<Blob> {
+        if let Some(algorithm) = self.compression.take() {
+            self.content = decompress(&algorithm, &self.content)?;
+        }
+        Ok(self)
+    }
+}
+
+
+/// Backend used to store and retrieve files.
+pub trait Storage: Send + Sync {
+    /// Returns the file stored at `path`, or `None` if there is no such file.
+    fn get(&self, path: &str) -> Result<Option<Blob>>;
+
+    /// Returns `length` bytes of the raw content of the file stored at `path`, starting at
+    /// `start`.
+    ///
+    /// The default implementation fetches the whole file, backends able to do ranged reads
+    /// should override it.
+    fn get_range(&self, path: &str, start: u64, length: u64) -> Result<Option<Vec<u8>>> {
+        Ok(self.get(path)?.map(|blob| {
+            let start = (start as usize).min(blob.content.len());
+            let end = (start + length as usize).min(blob.content.len());
+            blob.content[start..end].to_vec()
+        }))
+    }
+
+    /// Stores all the given files, replacing any existing file with the same path.
+    ///
+    /// `date_updated` of the blobs is ignored, the backend records the time of the upload.
+    fn store(&self, blobs: &[Blob]) -> Result<()>;
+
+    /// Stores all the given files as a single `archive`, which is replaced if it already exists.
+    ///
+    /// Only archive storage packs the files, other backends store them one by one.
+    fn store_archive(&self, _archive: &str, blobs: &[Blob]) -> Result<()> {
+        self.store(blobs)
+    }
+
+    /// Returns whether `store_archive` packs the files. Files are uploaded in batches into
+    /// backends which don't, instead of being read into memory all at once.
+    fn supports_archives(&self) -> bool {
+        false
+    }
+
+    /// Deletes every file whose path starts with `prefix`.
+    fn delete_prefix(&self, prefix: &str) -> Result<()>;
+}
+
+
+/// Creates the storage backend configured through environment variables.
+///
+/// `CRATESFYI_STORAGE_BACKEND` can be set to `database`, `s3` or `local`. If it's not set, S3 is
+/// used when AWS credentials are configured and the database otherwise. The local backend stores
+/// files in `CRATESFYI_STORAGE_PATH`, or in `$CRATESFYI_PREFIX/storage` if that's not set.
+///
+/// If `CRATESFYI_DEDUP_STORAGE` is set, identical files are only stored once in the selected
+/// backend. If `CRATESFYI_ARCHIVE_STORAGE` is set, the docs and sources of a release are stored
+/// in a single archive.
+pub fn from_env() -> Result<Arc<dyn Storage>> {
+    let backend = env::var("CRATESFYI_STORAGE_BACKEND").ok();
+    let storage: Arc<dyn Storage> = match backend.as_ref().map(|b| b.as_str()) {
+        Some("database") => Arc::new(DatabaseStorage::new(create_pool())),
+        Some("s3") => Arc::new(S3Storage::from_env()?),
+        Some("local") => Arc::new(LocalStorage::new(local_storage_path()?)),
+        Some(other) => bail!("unknown storage backend '{}'", other),
+        None => {
+            // If AWS keys aren't configured, then presume we should use the DB exclusively
+            // for file storage.
+            if env::var_os("AWS_ACCESS_KEY_ID").is_none() && env::var_os("FORCE_S3").is_none() {
+                Arc::new(DatabaseStorage::new(create_pool()))
+            } else {
+                match S3Storage::from_env() {
+                    Ok(s3) => Arc::new(s3),
+                    Err(err) => {
+                        warn!("failed to initialize S3 storage, using the database: {}", err);
+                        Arc::new(DatabaseStorage::new(create_pool()))
+                    }
+                }
+            }
+        }
+    };
+
+    let storage: Arc<dyn Storage> = if env::var_os("CRATESFYI_DEDUP_STORAGE").is_some() {
+        Arc::new(DedupStorage::new(storage, create_pool()))
+    } else {
+        storage
+    };
+
+    if env::var_os("CRATESFYI_ARCHIVE_STORAGE").is_some() {
+        Ok(Arc::new(ArchiveStorage::new(storage, create_pool())))
+    } else {
+        Ok(storage)
+    }
+}
+
+
+fn local_storage_path() -> Result<PathBuf> {
+    if let Ok(path) = env::var("CRATESFYI_STORAGE_PATH") {
+        Ok(PathBuf::from(path))
+    } else if let Ok(prefix) = env::var("CRATESFYI_PREFIX") {
+        Ok(PathBuf::from(prefix).join("storage"))
+    } else {
+        bail!("CRATESFYI_STORAGE_PATH or CRATESFYI_PREFIX must be set to use local storage")
+    }
+}
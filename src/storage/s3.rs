@@ -0,0 +1,280 @@
+//! Storage backend keeping files in an S3 bucket.
+
+use super::{Blob, Storage};
+use std::env;
+use std::io::Read;
+use std::str::FromStr;
+use error::Result;
+use futures::{future, stream, Future, Stream};
+use futures::future::Loop;
+use rusoto_core::region::Region;
+use rusoto_core::request::HttpClient;
+use rusoto_core::RusotoError;
+use rusoto_credential::DefaultCredentialsProvider;
+use rusoto_s3::{Delete, DeleteObjectsRequest, GetObjectError, GetObjectRequest,
+                ListObjectsV2Request, ObjectIdentifier, PutObjectRequest, S3, S3Client};
+use time;
+
+
+const DEFAULT_BUCKET: &'static str = "rust-docs-rs";
+const DEFAULT_REGION: &'static str = "us-west-1";
+/// Maximum number of files uploaded at the same time
+const MAX_CONCURRENT_UPLOADS: usize = 32;
+/// Number of times an upload is attempted before giving up
+const MAX_UPLOAD_ATTEMPTS: usize = 3;
+
+
+/// Location of the files stored in S3
+#[derive(Debug, Clone, PartialEq)]
+pub struct S3Config {
+    pub bucket: String,
+    pub region: Region,
+    /// Prefix added to the key of every stored file
+    pub prefix: String,
+}
+
+impl S3Config {
+    /// Reads the configuration from the environment.
+    ///
+    /// `S3_BUCKET`, `S3_REGION` and `S3_PREFIX` default to the `rust-docs-rs` bucket in
+    /// `us-west-1` without any prefix. `S3_ENDPOINT` can be used to point the client to an S3
+    /// compatible service, like MinIO.
+    pub fn from_env() -> Result<S3Config> {
+        S3Config::new(&env::var("S3_BUCKET").unwrap_or_else(|_| DEFAULT_BUCKET.to_owned()),
+                      &env::var("S3_REGION").unwrap_or_else(|_| DEFAULT_REGION.to_owned()),
+                      env::var("S3_ENDPOINT").ok(),
+                      &env::var("S3_PREFIX").unwrap_or_default())
+    }
+
+    pub fn new(bucket: &str,
+               region: &str,
+               endpoint: Option<String>,
+               prefix: &str)
+               -> Result<S3Config> {
+        let region = match endpoint {
+            Some(endpoint) => Region::Custom {
+                name: region.to_owned(),
+                endpoint: endpoint,
+            },
+            None => Region::from_str(region)
+                .map_err(|_| format_err!("invalid S3 region '{}'", region))?,
+        };
+
+        Ok(S3Config {
+            bucket: bucket.to_owned(),
+            region: region,
+            prefix: prefix.trim_matches('/').to_owned(),
+        })
+    }
+
+    /// Returns the S3 key used to store `path`
+    pub fn key(&self, path: &str) -> String {
+        if self.prefix.is_empty() {
+            path.to_owned()
+        } else {
+            format!("{}/{}", self.prefix, path)
+        }
+    }
+}
+
+
+pub struct S3Storage {
+    client: S3Client,
+    config: S3Config,
+}
+
+impl S3Storage {
+    /// Creates a new S3 client from the AWS credentials and `S3Config` in the environment.
+    pub fn from_env() -> Result<S3Storage> {
+        S3Storage::new(S3Config::from_env()?)
+    }
+
+    pub fn new(config: S3Config) -> Result<S3Storage> {
+        Ok(S3Storage {
+            client: new_client(&config.region)?,
+            config: config,
+        })
+    }
+
+    pub(crate) fn client(&self) -> &S3Client {
+        &self.client
+    }
+
+    pub(crate) fn config(&self) -> &S3Config {
+        &self.config
+    }
+}
+
+impl Storage for S3Storage {
+    fn get(&self, path: &str) -> Result<Option<Blob>> {
+        let res = self.client.get_object(GetObjectRequest {
+            bucket: self.config.bucket.clone(),
+            key: self.config.key(path),
+            ..Default::default()
+        }).sync();
+
+        let res = match res {
+            Ok(r) => r,
+            Err(RusotoError::Service(GetObjectError::NoSuchKey(_))) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut content = Vec::new();
+        if let Some(body) = res.body {
+            body.into_blocking_read().read_to_end(&mut content)?;
+        }
+
+        let last_modified = res.last_modified
+            .ok_or_else(|| format_err!("{} has no last modified date", path))?;
+        let last_modified = time::strptime(&last_modified, "%a, %d %b %Y %H:%M:%S %Z")
+            .map_err(|e| format_err!("failed to parse {:?} as timespec: {:?}", last_modified, e))?
+            .to_timespec();
+
+        Ok(Some(Blob {
+            path: path.into(),
+            // objects uploaded without a content type are served as binary files
+            mime: res.content_type.unwrap_or_else(|| "application/octet-stream".to_owned()),
+            date_updated: last_modified,
+            content,
+            compression: res.content_encoding,
+        }))
+    }
+
+    fn get_range(&self, path: &str, start: u64, length: u64) -> Result<Option<Vec<u8>>> {
+        if length == 0 {
+            return Ok(Some(Vec::new()));
+        }
+
+        let res = self.client.get_object(GetObjectRequest {
+            bucket: self.config.bucket.clone(),
+            key: self.config.key(path),
+            range: Some(format!("bytes={}-{}", start, start + length - 1)),
+            ..Default::default()
+        }).sync();
+
+        let res = match res {
+            Ok(r) => r,
+            Err(RusotoError::Service(GetObjectError::NoSuchKey(_))) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut content = Vec::new();
+        if let Some(body) = res.body {
+            body.into_blocking_read().read_to_end(&mut content)?;
+        }
+        Ok(Some(content))
+    }
+
+    fn store(&self, blobs: &[Blob]) -> Result<()> {
+        let uploads = blobs.iter().map(|blob| {
+            let client = self.client.clone();
+            let region = self.config.region.clone();
+            let bucket = self.config.bucket.clone();
+            let key = self.config.key(&blob.path);
+            let path = blob.path.clone();
+            let mime = blob.mime.clone();
+            let compression = blob.compression.clone();
+            let content = blob.content.clone();
+
+            future::loop_fn((client, 1), move |(client, attempt)| {
+                let path = path.clone();
+                let region = region.clone();
+                client.put_object(PutObjectRequest {
+                    bucket: bucket.clone(),
+                    key: key.clone(),
+                    body: Some(content.clone().into()),
+                    content_type: Some(mime.clone()),
+                    content_encoding: compression.clone(),
+                    ..Default::default()
+                }).then(move |res| match res {
+                    Ok(_) => Ok(Loop::Break(())),
+                    Err(ref e) if attempt >= MAX_UPLOAD_ATTEMPTS => {
+                        Err(format_err!("failed to upload {} {} times: {:?}", path, attempt, e))
+                    }
+                    Err(e) => {
+                        warn!("failed to upload {}, retrying: {:?}", path, e);
+                        // Get a new client, in case the old one's connection is stale.
+                        // AWS will kill our connection if it's alive for too long; this avoids
+                        // that preventing us from building the crate entirely.
+                        Ok(Loop::Continue((new_client(&region)?, attempt + 1)))
+                    }
+                })
+            })
+        }).collect::<Vec<_>>();
+
+        let mut rt = ::tokio::runtime::Runtime::new()?;
+        rt.block_on(stream::iter_ok(uploads)
+                        .buffer_unordered(MAX_CONCURRENT_UPLOADS)
+                        .for_each(|()| Ok(())))?;
+        Ok(())
+    }
+
+    fn delete_prefix(&self, prefix: &str) -> Result<()> {
+        let mut continuation_token = None;
+        loop {
+            let list = self.client.list_objects_v2(ListObjectsV2Request {
+                bucket: self.config.bucket.clone(),
+                prefix: Some(self.config.key(prefix)),
+                continuation_token: continuation_token,
+                ..Default::default()
+            }).sync()?;
+
+            // a listing returns at most 1000 keys, which is also the limit of a delete request
+            let objects: Vec<ObjectIdentifier> = list.contents
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|object| object.key)
+                .map(|key| ObjectIdentifier { key: key, version_id: None })
+                .collect();
+            if !objects.is_empty() {
+                let res = self.client.delete_objects(DeleteObjectsRequest {
+                    bucket: self.config.bucket.clone(),
+                    delete: Delete { objects: objects, quiet: Some(true) },
+                    ..Default::default()
+                }).sync()?;
+                if let Some(error) = res.errors.and_then(|errors| errors.into_iter().next()) {
+                    bail!("failed to delete {:?}: {:?}", error.key, error.message);
+                }
+            }
+
+            continuation_token = list.next_continuation_token;
+            if continuation_token.is_none() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+
+fn new_client(region: &Region) -> Result<S3Client> {
+    let creds = DefaultCredentialsProvider::new()?;
+    Ok(S3Client::new_with(HttpClient::new()?, creds, region.clone()))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::S3Config;
+    use rusoto_core::region::Region;
+
+    #[test]
+    fn test_config() {
+        let config = S3Config::new("rust-docs-rs", "us-west-1", None, "").unwrap();
+        assert_eq!(config.region, Region::UsWest1);
+        assert_eq!(config.key("rustdoc/foo/0.1.0/index.html"), "rustdoc/foo/0.1.0/index.html");
+
+        let config = S3Config::new("docs",
+                                   "local",
+                                   Some("http://localhost:9000".to_owned()),
+                                   "/mirror/").unwrap();
+        assert_eq!(config.bucket, "docs");
+        assert_eq!(config.region, Region::Custom {
+            name: "local".to_owned(),
+            endpoint: "http://localhost:9000".to_owned(),
+        });
+        assert_eq!(config.key("rustdoc/foo/0.1.0/index.html"),
+                   "mirror/rustdoc/foo/0.1.0/index.html");
+
+        assert!(S3Config::new("docs", "not-a-region", None, "").is_err());
+    }
+}
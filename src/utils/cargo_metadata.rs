@@ -90,6 +90,8 @@ pub(crate) struct Dependency {
     pub(crate) name: String,
     pub(crate) req: String,
     pub(crate) kind: Option<String>,
+    /// Platform the dependency is only used on, like `cfg(windows)`
+    pub(crate) target: Option<String>,
 }
 
 #[derive(RustcDecodable)]
@@ -7,7 +7,10 @@ pub use self::release_activity_updater::update_release_activity;
 pub use self::daemon::start_daemon;
 pub use self::rustc_version::{parse_rustc_version, get_current_versions, command_result};
 pub use self::html::extract_head_and_body;
-pub use self::queue::add_crate_to_queue;
+pub use self::queue::{add_crate_to_queue, get_queued_crates, remove_crate_from_queue,
+                      set_queue_priority, retry_failed_crates, QueuedCrate, MAX_ATTEMPTS};
+pub use self::rebuild::{start_rebuild_campaign, get_rebuild_campaigns, RebuildSelector,
+                        RebuildCampaign, REBUILD_PRIORITY};
 pub(crate) use self::cargo_metadata::{CargoMetadata, Package as MetadataPackage};
 
 mod cargo_metadata;
@@ -19,3 +22,4 @@ mod pubsubhubbub;
 mod rustc_version;
 mod html;
 mod queue;
+mod rebuild;
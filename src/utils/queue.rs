@@ -1,10 +1,124 @@
 //! Utilities for interacting with the build queue
 
 use postgres::Connection;
+use rustc_serialize::json::{Json, ToJson};
+use std::collections::BTreeMap;
 use error::Result;
+use time;
+
+
+/// Number of failed attempts after which a crate isn't built anymore
+pub const MAX_ATTEMPTS: i32 = 5;
+
+
+/// A crate in the build queue
+pub struct QueuedCrate {
+    pub id: i32,
+    pub name: String,
+    pub version: String,
+    pub priority: i32,
+    pub attempt: i32,
+    pub date_added: time::Timespec,
+    pub claimed_by: Option<String>,
+    pub last_error: Option<String>,
+    pub next_attempt_at: Option<time::Timespec>,
+}
+
+impl QueuedCrate {
+    /// Returns `true` if the crate failed too many times to be built again
+    pub fn is_failed(&self) -> bool {
+        self.attempt >= MAX_ATTEMPTS
+    }
+}
+
+impl ToJson for QueuedCrate {
+    fn to_json(&self) -> Json {
+        let mut m: BTreeMap<String, Json> = BTreeMap::new();
+        m.insert("id".to_string(), self.id.to_json());
+        m.insert("name".to_string(), self.name.to_json());
+        m.insert("version".to_string(), self.version.to_json());
+        m.insert("priority".to_string(), self.priority.to_json());
+        m.insert("attempt".to_string(), self.attempt.to_json());
+        m.insert("failed".to_string(), self.is_failed().to_json());
+        m.insert("date_added".to_string(),
+                 format!("{}", time::at(self.date_added).rfc3339()).to_json());
+        m.insert("claimed_by".to_string(), self.claimed_by.to_json());
+        m.insert("last_error".to_string(), self.last_error.to_json());
+        m.insert("next_attempt_at".to_string(),
+                 self.next_attempt_at
+                     .map(|t| format!("{}", time::at(t).rfc3339()))
+                     .to_json());
+        m.to_json()
+    }
+}
+
 
 pub fn add_crate_to_queue(conn: &Connection, name: &str, version: &str, priority: i32) -> Result<()> {
     try!(conn.execute("INSERT INTO queue (name, version, priority) VALUES ($1, $2, $3)",
                       &[&name, &version, &priority]));
     Ok(())
 }
+
+
+/// Returns the crates in the queue in build order, or only the ones which failed too many times
+pub fn get_queued_crates(conn: &Connection, failed_only: bool) -> Result<Vec<QueuedCrate>> {
+    let rows = try!(conn.query("SELECT id, name, version, priority, attempt, date_added,
+                                       claimed_by, last_error, next_attempt_at
+                                FROM queue
+                                WHERE NOT $1 OR attempt >= $2
+                                ORDER BY priority ASC, attempt ASC, id ASC",
+                               &[&failed_only, &MAX_ATTEMPTS]));
+
+    Ok(rows.iter()
+        .map(|row| {
+            QueuedCrate {
+                id: row.get(0),
+                name: row.get(1),
+                version: row.get(2),
+                priority: row.get::<_, Option<i32>>(3).unwrap_or(0),
+                attempt: row.get::<_, Option<i32>>(4).unwrap_or(0),
+                date_added: row.get(5),
+                claimed_by: row.get(6),
+                last_error: row.get(7),
+                next_attempt_at: row.get(8),
+            }
+        })
+        .collect())
+}
+
+
+/// Removes a crate from the queue, or only one of its versions. Returns the number of removed
+/// rows.
+pub fn remove_crate_from_queue(conn: &Connection,
+                               name: &str,
+                               version: Option<&str>)
+                               -> Result<u64> {
+    Ok(try!(conn.execute("DELETE FROM queue
+                          WHERE name = $1 AND ($2::VARCHAR IS NULL OR version = $2)",
+                         &[&name, &version])))
+}
+
+
+/// Changes the priority of a queued crate, or only of one of its versions. Returns the number
+/// of updated rows.
+pub fn set_queue_priority(conn: &Connection,
+                          name: &str,
+                          version: Option<&str>,
+                          priority: i32)
+                          -> Result<u64> {
+    Ok(try!(conn.execute("UPDATE queue
+                          SET priority = $3
+                          WHERE name = $1 AND ($2::VARCHAR IS NULL OR version = $2)",
+                         &[&name, &version, &priority])))
+}
+
+
+/// Resets the attempts of the crates which failed too many times, so they are built again.
+/// Only the versions of `name` are reset if it's set. Returns the number of updated rows.
+pub fn retry_failed_crates(conn: &Connection, name: Option<&str>) -> Result<u64> {
+    Ok(try!(conn.execute("UPDATE queue
+                          SET attempt = 0,
+                              next_attempt_at = NULL
+                          WHERE attempt >= $1 AND ($2::VARCHAR IS NULL OR name = $2)",
+                         &[&MAX_ATTEMPTS, &name])))
+}
@@ -0,0 +1,221 @@
+//! Rebuild campaigns, queueing existing releases again after a toolchain or rustdoc upgrade
+
+use postgres::Connection;
+use rustc_serialize::json::{Json, ToJson};
+use std::collections::BTreeMap;
+use error::Result;
+use time;
+use super::queue::MAX_ATTEMPTS;
+
+
+/// Priority of the releases queued by a rebuild campaign, after new releases and manual builds
+pub const REBUILD_PRIORITY: i32 = 20;
+
+
+/// Selects the releases queued by a rebuild campaign. Every set selector must match.
+#[derive(Default)]
+pub struct RebuildSelector {
+    /// Releases documented with a rustc older than this version, like `1.40.0`
+    pub rustc_before: Option<String>,
+    /// Releases whose last build is older than this date
+    pub built_before: Option<time::Timespec>,
+    /// Only releases which failed to build
+    pub failed_only: bool,
+    /// Only the latest release of every crate
+    pub latest_only: bool,
+    /// Releases of the `n` crates with the most GitHub stars
+    pub top: Option<i64>,
+    /// Releases of these crates
+    pub crates: Option<Vec<String>>,
+}
+
+impl RebuildSelector {
+    /// Returns a short description of the selectors, used when no campaign description is given
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(ref version) = self.rustc_before {
+            parts.push(format!("built before rustc {}", version));
+        }
+        if let Some(date) = self.built_before {
+            parts.push(format!("built before {}",
+                               time::at_utc(date).strftime("%Y-%m-%d").unwrap()));
+        }
+        if self.failed_only {
+            parts.push("failed builds".to_owned());
+        }
+        if self.latest_only {
+            parts.push("latest releases".to_owned());
+        }
+        if let Some(top) = self.top {
+            parts.push(format!("top {} crates", top));
+        }
+        if let Some(ref crates) = self.crates {
+            parts.push(format!("{} listed crates", crates.len()));
+        }
+
+        if parts.is_empty() {
+            "all releases".to_owned()
+        } else {
+            parts.join(", ")
+        }
+    }
+
+    /// Returns the name and version of the matching releases which aren't already queued
+    pub fn select_releases(&self, conn: &Connection) -> Result<Vec<(String, String)>> {
+        if let Some(ref version) = self.rustc_before {
+            let parts: Vec<&str> = version.split('.').collect();
+            if parts.len() != 3 || parts.iter().any(|n| n.parse::<u32>().is_err()) {
+                bail!("invalid rustc version {}, expected a version like 1.40.0", version);
+            }
+        }
+
+        let rows = try!(conn.query(
+            "SELECT crates.name, releases.version
+             FROM releases
+             INNER JOIN crates ON releases.crate_id = crates.id
+             WHERE NOT releases.yanked AND
+                   ($1::VARCHAR IS NULL OR
+                    string_to_array(substring(releases.doc_rustc_version
+                                              FROM 'rustc (\\d+\\.\\d+\\.\\d+)'), '.')::INT[] <
+                    string_to_array($1, '.')::INT[]) AND
+                   ($2::TIMESTAMP IS NULL OR
+                    (SELECT MAX(build_time) FROM builds WHERE builds.rid = releases.id) < $2) AND
+                   (NOT $3 OR NOT releases.build_status) AND
+                   (NOT $4 OR releases.id = crates.latest_version_id) AND
+                   ($5::BIGINT IS NULL OR crates.id IN (SELECT id
+                                                        FROM crates
+                                                        ORDER BY github_stars DESC NULLS LAST
+                                                        LIMIT $5)) AND
+                   ($6::VARCHAR[] IS NULL OR crates.name = ANY($6)) AND
+                   NOT EXISTS (SELECT 1
+                               FROM queue
+                               WHERE queue.name = crates.name AND
+                                     queue.version = releases.version)
+             ORDER BY crates.github_stars DESC NULLS LAST, releases.release_time DESC",
+            &[&self.rustc_before,
+              &self.built_before,
+              &self.failed_only,
+              &self.latest_only,
+              &self.top,
+              &self.crates]));
+
+        Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+    }
+}
+
+
+/// Progress of a rebuild campaign
+pub struct RebuildCampaign {
+    pub id: i32,
+    pub description: String,
+    pub created_at: time::Timespec,
+    /// Number of releases queued by the campaign
+    pub total: i32,
+    /// Number of releases built by the campaign
+    pub built: i32,
+    /// Number of releases still in the queue, including the failed ones. Releases removed from
+    /// the queue are neither built nor remaining.
+    pub remaining: i64,
+    /// Number of releases which failed to build too many times
+    pub failed: i64,
+}
+
+impl ToJson for RebuildCampaign {
+    fn to_json(&self) -> Json {
+        let mut m: BTreeMap<String, Json> = BTreeMap::new();
+        m.insert("id".to_string(), self.id.to_json());
+        m.insert("description".to_string(), self.description.to_json());
+        m.insert("created_at".to_string(),
+                 format!("{}", time::at(self.created_at).rfc3339()).to_json());
+        m.insert("total".to_string(), self.total.to_json());
+        m.insert("built".to_string(), self.built.to_json());
+        m.insert("remaining".to_string(), self.remaining.to_json());
+        m.insert("failed".to_string(), self.failed.to_json());
+        m.to_json()
+    }
+}
+
+
+/// Queues the releases matching `selector` with `priority`, and records them as a new campaign.
+/// Returns the id of the campaign and the number of queued releases.
+pub fn start_rebuild_campaign(conn: &Connection,
+                              selector: &RebuildSelector,
+                              description: Option<&str>,
+                              priority: i32)
+                              -> Result<(i32, usize)> {
+    let releases = try!(selector.select_releases(conn));
+    let description = description.map(|d| d.to_owned()).unwrap_or_else(|| selector.describe());
+
+    let trans = try!(conn.transaction());
+    let campaign_id: i32 = try!(trans.query("INSERT INTO rebuild_campaigns (description, total)
+                                             VALUES ($1, $2)
+                                             RETURNING id",
+                                            &[&description, &(releases.len() as i32)]))
+        .get(0)
+        .get(0);
+    {
+        let statement = try!(trans.prepare("INSERT INTO queue
+                                                (name, version, priority, campaign_id)
+                                            VALUES ($1, $2, $3, $4)"));
+        for &(ref name, ref version) in &releases {
+            try!(statement.execute(&[name, version, &priority, &campaign_id]));
+        }
+    }
+    try!(trans.commit());
+
+    info!("rebuild campaign {} ({}) queued {} releases",
+          campaign_id,
+          description,
+          releases.len());
+    Ok((campaign_id, releases.len()))
+}
+
+
+/// Returns the progress of every rebuild campaign, newest first
+pub fn get_rebuild_campaigns(conn: &Connection) -> Result<Vec<RebuildCampaign>> {
+    let rows = try!(conn.query("SELECT rebuild_campaigns.id,
+                                       rebuild_campaigns.description,
+                                       rebuild_campaigns.created_at,
+                                       rebuild_campaigns.total,
+                                       rebuild_campaigns.built,
+                                       COUNT(queue.id),
+                                       COUNT(queue.id) FILTER (WHERE queue.attempt >= $1)
+                                FROM rebuild_campaigns
+                                LEFT JOIN queue ON queue.campaign_id = rebuild_campaigns.id
+                                GROUP BY rebuild_campaigns.id
+                                ORDER BY rebuild_campaigns.id DESC",
+                               &[&MAX_ATTEMPTS]));
+
+    Ok(rows.iter()
+        .map(|row| {
+            RebuildCampaign {
+                id: row.get(0),
+                description: row.get(1),
+                created_at: row.get(2),
+                total: row.get(3),
+                built: row.get(4),
+                remaining: row.get(5),
+                failed: row.get(6),
+            }
+        })
+        .collect())
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::RebuildSelector;
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(RebuildSelector::default().describe(), "all releases");
+
+        let selector = RebuildSelector {
+            rustc_before: Some("1.40.0".to_owned()),
+            failed_only: true,
+            top: Some(100),
+            ..Default::default()
+        };
+        assert_eq!(selector.describe(), "built before rustc 1.40.0, failed builds, top 100 crates");
+    }
+}
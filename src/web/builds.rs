@@ -1,6 +1,6 @@
 
 
-use docbuilder::Limits;
+use docbuilder::{FailureKind, Limits};
 use std::collections::BTreeMap;
 use super::MetaData;
 use super::pool::Pool;
@@ -19,15 +19,26 @@ struct Build {
     rustc_version: String,
     cratesfyi_version: String,
     build_status: bool,
+    failure_kind: Option<FailureKind>,
     build_time: time::Timespec,
     output: Option<String>,
 }
 
 
+struct BuildStep {
+    name: String,
+    duration_ms: i64,
+    successful: bool,
+    error: Option<String>,
+    output: Option<String>,
+}
+
+
 struct BuildsPage {
     metadata: Option<MetaData>,
     builds: Vec<Build>,
     build_details: Option<Build>,
+    build_steps: Vec<BuildStep>,
     limits: Limits,
 }
 
@@ -40,6 +51,10 @@ impl ToJson for Build {
         m.insert("cratesfyi_version".to_owned(),
                  self.cratesfyi_version.to_json());
         m.insert("build_status".to_owned(), self.build_status.to_json());
+        m.insert("failure_kind".to_owned(),
+                 self.failure_kind.map(|kind| kind.as_str()).to_json());
+        m.insert("failure_description".to_owned(),
+                 self.failure_kind.map(|kind| kind.description()).to_json());
         m.insert("build_time".to_owned(),
                  format!("{}", time::at(self.build_time).rfc3339()).to_json());
         m.insert("build_time_relative".to_owned(),
@@ -50,12 +65,27 @@ impl ToJson for Build {
 }
 
 
+impl ToJson for BuildStep {
+    fn to_json(&self) -> Json {
+        let mut m: BTreeMap<String, Json> = BTreeMap::new();
+        m.insert("name".to_owned(), self.name.to_json());
+        m.insert("duration".to_owned(),
+                 format!("{:.1}s", self.duration_ms as f64 / 1000.0).to_json());
+        m.insert("successful".to_owned(), self.successful.to_json());
+        m.insert("error".to_owned(), self.error.to_json());
+        m.insert("output".to_owned(), self.output.to_json());
+        m.to_json()
+    }
+}
+
+
 impl ToJson for BuildsPage {
     fn to_json(&self) -> Json {
         let mut m: BTreeMap<String, Json> = BTreeMap::new();
         m.insert("metadata".to_owned(), self.metadata.to_json());
         m.insert("builds".to_owned(), self.builds.to_json());
         m.insert("build_details".to_owned(), self.build_details.to_json());
+        m.insert("build_steps".to_owned(), self.build_steps.to_json());
         m.insert("limits".into(), self.limits.for_website().to_json());
         m.to_json()
     }
@@ -70,7 +100,7 @@ pub fn build_list_handler(req: &mut Request) -> IronResult<Response> {
     let req_build_id: i32 = router.find("id").unwrap_or("0").parse().unwrap_or(0);
 
     let conn = extension!(req, Pool);
-    let limits = ctry!(Limits::for_crate(&conn, name));
+    let limits = ctry!(Limits::for_crate(&conn, name, Some(version)));
 
     let mut build_list: Vec<Build> = Vec::new();
     let mut build_details = None;
@@ -86,7 +116,8 @@ pub fn build_list_handler(req: &mut Request) -> IronResult<Response> {
                                          builds.cratesfyi_version,
                                          builds.build_status,
                                          builds.build_time,
-                                         builds.output
+                                         builds.output,
+                                         builds.failure_kind
                                   FROM builds
                                   INNER JOIN releases ON releases.id = builds.rid
                                   INNER JOIN crates ON releases.crate_id = crates.id
@@ -103,6 +134,8 @@ pub fn build_list_handler(req: &mut Request) -> IronResult<Response> {
             build_status: row.get(8),
             build_time: row.get(9),
             output: row.get(10),
+            failure_kind: row.get::<_, Option<String>>(11)
+                .and_then(|kind| FailureKind::from_str(&kind)),
         };
 
         if id == req_build_id {
@@ -131,10 +164,28 @@ pub fn build_list_handler(req: &mut Request) -> IronResult<Response> {
         resp.headers.set(AccessControlAllowOrigin::Any);
         Ok(resp)
     } else {
+        let mut build_steps = Vec::new();
+        if let Some(ref build) = build_details {
+            for row in &ctry!(conn.query("SELECT name, duration_ms, successful, error, output
+                                          FROM build_steps
+                                          WHERE build_id = $1
+                                          ORDER BY position",
+                                         &[&build.id])) {
+                build_steps.push(BuildStep {
+                    name: row.get(0),
+                    duration_ms: row.get(1),
+                    successful: row.get(2),
+                    error: row.get(3),
+                    output: row.get(4),
+                });
+            }
+        }
+
         let builds_page = BuildsPage {
             metadata: MetaData::from_crate(&conn, &name, &version),
             builds: build_list,
             build_details: build_details,
+            build_steps,
             limits,
         };
         Page::new(builds_page)
@@ -47,6 +47,7 @@ pub struct CrateDetails {
     doc_targets: Option<Json>,
     license: Option<String>,
     documentation_url: Option<String>,
+    yanked: bool,
 }
 
 
@@ -85,6 +86,7 @@ impl ToJson for CrateDetails {
         m.insert("doc_targets".to_string(), self.doc_targets.to_json());
         m.insert("license".to_string(), self.license.to_json());
         m.insert("documentation_url".to_string(), self.documentation_url.to_json());
+        m.insert("yanked".to_string(), self.yanked.to_json());
         m.to_json()
     }
 }
@@ -118,7 +120,8 @@ impl CrateDetails {
                             releases.is_library,
                             releases.doc_targets,
                             releases.license,
-                            releases.documentation_url
+                            releases.documentation_url,
+                            releases.yanked
                      FROM releases
                      INNER JOIN crates ON releases.crate_id = crates.id
                      WHERE crates.name = $1 AND releases.version = $2;";
@@ -188,6 +191,7 @@ impl CrateDetails {
             doc_targets: rows.get(0).get(22),
             license: rows.get(0).get(23),
             documentation_url: rows.get(0).get(24),
+            yanked: rows.get(0).get::<_, Option<bool>>(25).unwrap_or(false),
         };
 
         if let Some(repository_url) = crate_details.repository_url.clone() {
@@ -1,36 +1,67 @@
 //! Database based file handler
 
-use super::pool::Pool;
+use super::storage::FileStorage;
 use time;
-use postgres::Connection;
 use iron::{Handler, Request, IronResult, Response, IronError};
 use iron::status;
-use crate::db;
+use storage::{Blob, Storage};
 
 
-pub struct File(pub db::file::Blob);
+pub struct File(pub Blob);
 
 impl File {
-    /// Gets file from database
-    pub fn from_path(conn: &Connection, path: &str) -> Option<File> {
-        Some(File(db::file::get_path(conn, path)?))
+    /// Gets file from the storage
+    pub fn from_path(storage: &dyn Storage, path: &str) -> Option<File> {
+        match storage.get(path) {
+            Ok(blob) => blob.map(File),
+            Err(e) => {
+                error!("Failed to get {} from storage: {}", path, e);
+                None
+            }
+        }
     }
 
 
     /// Consumes File and creates a iron response
-    pub fn serve(self) -> Response {
-        use iron::headers::{CacheControl, LastModified, CacheDirective, HttpDate, ContentType};
+    ///
+    /// Compressed files are sent as they are stored if the client accepts their encoding, and
+    /// decompressed otherwise.
+    pub fn serve(self, req: &Request) -> Response {
+        use iron::headers::{CacheControl, LastModified, CacheDirective, HttpDate, ContentType,
+                            ContentEncoding};
+
+        let file = if self.0.compression.as_ref().map_or(false, |c| accepts_encoding(req, c)) {
+            self.0
+        } else {
+            match self.0.decompress() {
+                Ok(blob) => blob,
+                Err(e) => {
+                    error!("Failed to decompress file: {}", e);
+                    return Response::with(status::InternalServerError);
+                }
+            }
+        };
 
-        let mut response = Response::with((status::Ok, self.0.content));
+        let mut response = Response::with((status::Ok, file.content));
         let cache = vec![CacheDirective::Public,
                          CacheDirective::MaxAge(super::STATIC_FILE_CACHE_DURATION as u32)];
-        response.headers.set(ContentType(self.0.mime.parse().unwrap()));
+        response.headers.set(ContentType(file.mime.parse().unwrap()));
         response.headers.set(CacheControl(cache));
-        response.headers.set(LastModified(HttpDate(time::at(self.0.date_updated))));
+        response.headers.set(LastModified(HttpDate(time::at(file.date_updated))));
+        if let Some(compression) = file.compression {
+            response.headers.set(ContentEncoding(vec![compression.parse().unwrap()]));
+        }
+        response.headers.set_raw("Vary", vec![b"Accept-Encoding".to_vec()]);
         response
     }
 
 
+    /// Consumes File and returns it with its content decompressed
+    pub fn decompress(self) -> ::error::Result<File> {
+        self.0.decompress().map(File)
+    }
+
+
     /// Checks if mime type of file is "application/x-empty"
     pub fn is_empty(&self) -> bool {
         self.0.mime == "application/x-empty"
@@ -38,17 +69,31 @@ impl File {
 }
 
 
+/// Checks if the client accepts responses compressed with `encoding`
+fn accepts_encoding(req: &Request, encoding: &str) -> bool {
+    use iron::headers::{AcceptEncoding, Encoding, Quality};
+
+    let encoding: Encoding = match encoding.parse() {
+        Ok(encoding) => encoding,
+        Err(_) => return false,
+    };
+    req.headers.get::<AcceptEncoding>().map_or(false, |accepted| {
+        accepted.iter().any(|item| item.item == encoding && item.quality > Quality(0))
+    })
+}
+
+
 /// Database based file handler for iron
 ///
-/// This is similar to staticfile crate, but its using getting files from database.
+/// This is similar to staticfile crate, but its using getting files from the storage.
 pub struct DatabaseFileHandler;
 
 impl Handler for DatabaseFileHandler {
     fn handle(&self, req: &mut Request) -> IronResult<Response> {
         let path = req.url.path().join("/");
-        let conn = extension!(req, Pool);
-        if let Some(file) = File::from_path(&conn, &path) {
-            Ok(file.serve())
+        let storage = extension!(req, FileStorage);
+        if let Some(file) = File::from_path(&**storage, &path) {
+            Ok(file.serve(req))
         } else {
             Err(IronError::new(super::error::Nope::CrateNotFound, status::NotFound))
         }
@@ -40,6 +40,7 @@ mod releases;
 mod crate_details;
 mod source;
 mod pool;
+mod storage;
 mod file;
 mod builds;
 mod error;
@@ -62,6 +63,7 @@ use postgres::Connection;
 use semver::{Version, VersionReq};
 use rustc_serialize::json::{Json, ToJson};
 use std::collections::BTreeMap;
+use std::sync::Arc;
 
 /// Duration of static files for staticfile and DatabaseFileHandler (in seconds)
 const STATIC_FILE_CACHE_DURATION: u64 = 60 * 60 * 24 * 30 * 12;   // 12 months
@@ -74,11 +76,12 @@ struct CratesfyiHandler {
     router_handler: Box<dyn Handler>,
     database_file_handler: Box<dyn Handler>,
     static_handler: Box<dyn Handler>,
+    storage: Arc<dyn ::storage::Storage>,
 }
 
 
 impl CratesfyiHandler {
-    fn chain<H: Handler>(base: H) -> Chain {
+    fn chain<H: Handler>(storage: &Arc<dyn ::storage::Storage>, base: H) -> Chain {
         // TODO: Use DocBuilderOptions for paths
         let mut hbse = HandlebarsEngine::new();
         hbse.add(Box::new(DirectorySource::new("./templates", ".hbs")));
@@ -90,11 +93,12 @@ impl CratesfyiHandler {
 
         let mut chain = Chain::new(base);
         chain.link_before(pool::Pool::new());
+        chain.link_before(storage::FileStorage::new(storage.clone()));
         chain.link_after(hbse);
         chain
     }
 
-    pub fn new() -> CratesfyiHandler {
+    pub fn new(storage: Arc<dyn ::storage::Storage>) -> CratesfyiHandler {
         let mut router = Router::new();
         router.get("/", releases::home_page, "index");
         router.get("/style.css", style_css_handler, "style_css");
@@ -159,6 +163,9 @@ impl CratesfyiHandler {
         router.get("/crate/:name/:version/builds/:id",
                    builds::build_list_handler,
                    "crate_name_version_builds_id");
+        router.get("/crate/:name/:version/download",
+                   rustdoc::download_handler,
+                   "crate_name_version_download");
         router.get("/crate/:name/:version/source/",
                    source::source_browser_handler,
                    "crate_name_version_source");
@@ -190,8 +197,8 @@ impl CratesfyiHandler {
                    rustdoc::rustdoc_html_server_handler,
                    "crate_version_target_html");
 
-        let shared_resources = Self::chain(rustdoc::SharedResourceHandler);
-        let router_chain = Self::chain(router);
+        let shared_resources = Self::chain(&storage, rustdoc::SharedResourceHandler);
+        let router_chain = Self::chain(&storage, router);
         let prefix = PathBuf::from(env::var("CRATESFYI_PREFIX").unwrap()).join("public_html");
         let static_handler = Static::new(prefix)
             .cache(Duration::from_secs(STATIC_FILE_CACHE_DURATION));
@@ -201,6 +208,7 @@ impl CratesfyiHandler {
             router_handler: Box::new(router_chain),
             database_file_handler: Box::new(file::DatabaseFileHandler),
             static_handler: Box::new(static_handler),
+            storage: storage,
         }
     }
 }
@@ -257,7 +265,7 @@ impl Handler for CratesfyiHandler {
                 }
 
 
-                Self::chain(err).handle(req)
+                Self::chain(&self.storage, err).handle(req)
             })
     }
 }
@@ -318,6 +326,18 @@ fn match_version(conn: &Connection, name: &str, version: Option<&str>) -> MatchV
         versions
     };
 
+    let yanked_versions: Vec<String> = conn.query("SELECT releases.version
+                                                   FROM releases
+                                                   INNER JOIN crates
+                                                       ON releases.crate_id = crates.id
+                                                   WHERE crates.name = $1 AND
+                                                         releases.yanked = TRUE",
+                                                  &[&name])
+        .unwrap()
+        .iter()
+        .map(|row| row.get(0))
+        .collect();
+
     // first check for exact match
     // we can't expect users to use semver in query
     for version in &versions {
@@ -351,15 +371,21 @@ fn match_version(conn: &Connection, name: &str, version: Option<&str>) -> MatchV
         versions_sem
     };
 
-    // semver is acting weird for '*' (any) range if a crate only have pre-release versions
-    // return first version if requested version is '*'
-    if req_version == "*" && !versions_sem.is_empty() {
-        return MatchVersion::Semver(format!("{}", versions_sem[0]));
-    }
+    // yanked versions are only used if no other version matches
+    let (available, yanked): (Vec<Version>, Vec<Version>) = versions_sem.into_iter()
+        .partition(|version| !yanked_versions.contains(&format!("{}", version)));
+
+    for versions_sem in &[available, yanked] {
+        // semver is acting weird for '*' (any) range if a crate only have pre-release versions
+        // return first version if requested version is '*'
+        if req_version == "*" && !versions_sem.is_empty() {
+            return MatchVersion::Semver(format!("{}", versions_sem[0]));
+        }
 
-    for version in &versions_sem {
-        if req_sem_ver.matches(&version) {
-            return MatchVersion::Semver(format!("{}", version));
+        for version in versions_sem {
+            if req_sem_ver.matches(&version) {
+                return MatchVersion::Semver(format!("{}", version));
+            }
         }
     }
 
@@ -426,11 +452,33 @@ fn latest_version(versions_json: &Vec<String>, req_version: &str) -> Option<Stri
 
 /// Starts cratesfyi web server
 pub fn start_web_server(sock_addr: Option<&str>) {
-    let cratesfyi = CratesfyiHandler::new();
+    let storage = ::storage::from_env().expect("Failed to initialize file storage");
+    let cratesfyi = CratesfyiHandler::new(storage);
     Iron::new(cratesfyi).http(sock_addr.unwrap_or("localhost:3000")).unwrap();
 }
 
 
+/// Starts a web server only serving the files of `storage`, to preview the documentation built
+/// by `cratesfyi build local`. It doesn't need a database.
+pub fn start_preview_server(sock_addr: Option<&str>, storage: Arc<dyn ::storage::Storage>) {
+    let mut chain = Chain::new(PreviewHandler);
+    chain.link_before(storage::FileStorage::new(storage));
+    Iron::new(chain).http(sock_addr.unwrap_or("localhost:3000")).unwrap();
+}
+
+
+/// Serves the shared rustdoc resources and the documentation from the file storage
+struct PreviewHandler;
+
+impl Handler for PreviewHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        rustdoc::SharedResourceHandler
+            .handle(req)
+            .or_else(|_| file::DatabaseFileHandler.handle(req))
+    }
+}
+
+
 
 /// Converts Timespec to nice readable relative time string
 fn duration_to_str(ts: time::Timespec) -> String {
@@ -33,6 +33,7 @@ pub struct Page<T: ToJson> {
     varss: BTreeMap<String, String>,
     varsb: BTreeMap<String, bool>,
     varsi: BTreeMap<String, i64>,
+    varsj: BTreeMap<String, Json>,
 }
 
 
@@ -45,6 +46,7 @@ impl<T: ToJson> Page<T> {
             varss: BTreeMap::new(),
             varsb: BTreeMap::new(),
             varsi: BTreeMap::new(),
+            varsj: BTreeMap::new(),
         }
     }
 
@@ -76,6 +78,13 @@ impl<T: ToJson> Page<T> {
     }
 
 
+    /// Sets a JSON variable, like a list iterated over in the template
+    pub fn set_json(mut self, var: &str, val: Json) -> Page<T> {
+        &self.varsj.insert(var.to_owned(), val);
+        self
+    }
+
+
     /// Sets title of page
     pub fn title(mut self, title: &str) -> Page<T> {
         self.title = Some(title.to_owned());
@@ -120,6 +129,7 @@ impl<T: ToJson> ToJson for Page<T> {
         tree.insert("varss".to_owned(), self.varss.to_json());
         tree.insert("varsb".to_owned(), self.varsb.to_json());
         tree.insert("varsi".to_owned(), self.varsi.to_json());
+        tree.insert("varsj".to_owned(), self.varsj.to_json());
         Json::Object(tree)
     }
 }
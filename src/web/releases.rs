@@ -12,6 +12,7 @@ use rustc_serialize::json::{Json, ToJson};
 use std::collections::BTreeMap;
 use time;
 use postgres::Connection;
+use docbuilder::{FailureKind, HEARTBEAT_TIMEOUT};
 
 
 /// Number of release in home page
@@ -69,8 +70,9 @@ impl ToJson for Release {
 enum Order {
     ReleaseTime, // this is default order
     GithubStars,
-    RecentFailures,
-    FailuresByGithubStars,
+    /// Failed releases, optionally only the ones whose last build failed with this kind
+    RecentFailures(Option<FailureKind>),
+    FailuresByGithubStars(Option<FailureKind>),
 }
 
 
@@ -107,7 +109,7 @@ fn get_releases(conn: &Connection, page: i64, limit: i64, order: Order) -> Vec<R
              ORDER BY crates.github_stars DESC
              LIMIT $1 OFFSET $2"
         }
-        Order::RecentFailures => {
+        Order::RecentFailures(_) => {
             "SELECT crates.name,
                     releases.version,
                     releases.description,
@@ -117,11 +119,17 @@ fn get_releases(conn: &Connection, page: i64, limit: i64, order: Order) -> Vec<R
                     crates.github_stars
              FROM crates
              INNER JOIN releases ON crates.id = releases.crate_id
-             WHERE releases.build_status = FALSE AND releases.is_library = TRUE
+             WHERE releases.build_status = FALSE AND releases.is_library = TRUE AND
+                   ($3::VARCHAR IS NULL OR
+                    (SELECT builds.failure_kind
+                     FROM builds
+                     WHERE builds.rid = releases.id
+                     ORDER BY builds.build_time DESC
+                     LIMIT 1) = $3)
              ORDER BY releases.release_time DESC
              LIMIT $1 OFFSET $2"
         }
-        Order::FailuresByGithubStars => {
+        Order::FailuresByGithubStars(_) => {
             "SELECT crates.name,
                     releases.version,
                     releases.description,
@@ -131,14 +139,27 @@ fn get_releases(conn: &Connection, page: i64, limit: i64, order: Order) -> Vec<R
                     crates.github_stars
              FROM crates
              INNER JOIN releases ON releases.id = crates.latest_version_id
-             WHERE releases.build_status = FALSE AND releases.is_library = TRUE
+             WHERE releases.build_status = FALSE AND releases.is_library = TRUE AND
+                   ($3::VARCHAR IS NULL OR
+                    (SELECT builds.failure_kind
+                     FROM builds
+                     WHERE builds.rid = releases.id
+                     ORDER BY builds.build_time DESC
+                     LIMIT 1) = $3)
              ORDER BY crates.github_stars DESC
              LIMIT $1 OFFSET $2"
         }
     };
 
+    let rows = match order {
+        Order::RecentFailures(kind) | Order::FailuresByGithubStars(kind) => {
+            conn.query(&query, &[&limit, &offset, &kind.map(|kind| kind.as_str())])
+        }
+        _ => conn.query(&query, &[&limit, &offset]),
+    };
+
     let mut packages = Vec::new();
-    for row in &conn.query(&query, &[&limit, &offset]).unwrap() {
+    for row in &rows.unwrap() {
         let package = Release {
             name: row.get(0),
             version: row.get(1),
@@ -335,6 +356,15 @@ pub fn releases_handler(packages: Vec<Release>,
                         release_type: &str,
                         tab: &str,
                         title: &str) -> IronResult<Response> {
+    releases_page(packages, page_number, release_type, tab, title)?.to_resp("releases")
+}
+
+
+fn releases_page(packages: Vec<Release>,
+                 page_number: i64,
+                 release_type: &str,
+                 tab: &str,
+                 title: &str) -> IronResult<Page<Vec<Release>>> {
     if packages.is_empty() {
         return Err(IronError::new(Nope::CrateNotFound, status::NotFound));
     }
@@ -344,7 +374,7 @@ pub fn releases_handler(packages: Vec<Release>,
     let (show_next_page, show_previous_page) = (packages.len() == RELEASES_IN_RELEASES as usize,
                                                 page_number != 1);
 
-    Page::new(packages)
+    Ok(Page::new(packages)
         .title("Releases")
         .set("description", title)
         .set("release_type", release_type)
@@ -353,8 +383,51 @@ pub fn releases_handler(packages: Vec<Release>,
         .set_bool("show_next_page_button", show_next_page)
         .set_int("next_page", page_number + 1)
         .set_bool("show_previous_page_button", show_previous_page)
-        .set_int("previous_page", page_number - 1)
-        .to_resp("releases")
+        .set_int("previous_page", page_number - 1))
+}
+
+
+/// Renders a listing of failed releases, with links to filter them by failure kind
+fn failures_handler(packages: Vec<Release>,
+                    page_number: i64,
+                    release_type: &str,
+                    tab: &str,
+                    title: &str,
+                    failure_kind: Option<FailureKind>) -> IronResult<Response> {
+    let failure_kinds: Vec<Json> = FailureKind::all()
+        .iter()
+        .map(|kind| {
+            let mut m: BTreeMap<String, Json> = BTreeMap::new();
+            m.insert("url".to_string(),
+                     format!("/releases/{}?kind={}", release_type, kind.as_str()).to_json());
+            m.insert("description".to_string(), kind.description().to_json());
+            m.insert("active".to_string(), (Some(*kind) == failure_kind).to_json());
+            m.to_json()
+        })
+        .collect();
+    let mut page = releases_page(packages, page_number, release_type, tab, title)?
+        .set_true("show_failure_kinds")
+        .set_json("failure_kinds", failure_kinds.to_json());
+    if let Some(kind) = failure_kind {
+        page = page.set("failure_kind", kind.as_str())
+            .set("description", &format!("{} ({})", title, kind.description()));
+    }
+    page.to_resp("releases")
+}
+
+
+/// Returns the failure kind of the `kind` query parameter, if it's set
+fn failure_kind_param(req: &mut Request) -> IronResult<Option<FailureKind>> {
+    use params::{Params, Value};
+
+    let params = ctry!(req.get::<Params>());
+    match params.find(&["kind"]) {
+        Some(&Value::String(ref kind)) => match FailureKind::from_str(kind) {
+            Some(kind) => Ok(Some(kind)),
+            None => Err(IronError::new(Nope::ResourceNotFound, status::NotFound)),
+        },
+        _ => Ok(None),
+    }
 }
 
 
@@ -376,18 +449,20 @@ pub fn releases_by_stars_handler(req: &mut Request) -> IronResult<Response> {
 
 
 pub fn releases_recent_failures_handler(req: &mut Request) -> IronResult<Response> {
+    let failure_kind = failure_kind_param(req)?;
     let page_number: i64 = extension!(req, Router).find("page").unwrap_or("1").parse().unwrap_or(1);
     let conn = extension!(req, Pool);
-    let packages = get_releases(conn, page_number, RELEASES_IN_RELEASES, Order::RecentFailures);
-    releases_handler(packages, page_number, "recent-failures", "releases_navigation_recent_failures_tab", "Recent crates failed to build")
+    let packages = get_releases(conn, page_number, RELEASES_IN_RELEASES, Order::RecentFailures(failure_kind));
+    failures_handler(packages, page_number, "recent-failures", "releases_navigation_recent_failures_tab", "Recent crates failed to build", failure_kind)
 }
 
 
 pub fn releases_failures_by_stars_handler(req: &mut Request) -> IronResult<Response> {
+    let failure_kind = failure_kind_param(req)?;
     let page_number: i64 = extension!(req, Router).find("page").unwrap_or("1").parse().unwrap_or(1);
     let conn = extension!(req, Pool);
-    let packages = get_releases(conn, page_number, RELEASES_IN_RELEASES, Order::FailuresByGithubStars);
-    releases_handler(packages, page_number, "failures", "releases_navigation_failures_by_stars_tab", "Crates with most stars failed to build")
+    let packages = get_releases(conn, page_number, RELEASES_IN_RELEASES, Order::FailuresByGithubStars(failure_kind));
+    failures_handler(packages, page_number, "failures", "releases_navigation_failures_by_stars_tab", "Crates with most stars failed to build", failure_kind)
 }
 
 
@@ -556,16 +631,38 @@ pub fn activity_handler(req: &mut Request) -> IronResult<Response> {
 pub fn build_queue_handler(req: &mut Request) -> IronResult<Response> {
     let conn = extension!(req, Pool);
     let mut crates: Vec<(String, String)> = Vec::new();
-    for krate in &conn.query("SELECT name, version
-                              FROM queue
-                              WHERE attempt < 5
-                              ORDER BY priority ASC, attempt ASC, id ASC",
+    let mut building: Vec<Json> = Vec::new();
+    for krate in &conn.query(&format!("SELECT name, version, claimed_by, claimed_at,
+                                              claimed_at IS NOT NULL AND
+                                              COALESCE(heartbeat, claimed_at) >=
+                                                  NOW() - INTERVAL '{}'
+                                       FROM queue
+                                       WHERE attempt < 5
+                                       ORDER BY priority ASC, attempt ASC, id ASC",
+                                      HEARTBEAT_TIMEOUT),
                &[])
         .unwrap() {
-        crates.push((krate.get(0), krate.get(1)));
+        if krate.get(4) {
+            let name: String = krate.get(0);
+            let version: String = krate.get(1);
+            let claimed_by: Option<String> = krate.get(2);
+            let mut m: BTreeMap<String, Json> = BTreeMap::new();
+            m.insert("name".to_owned(), name.to_json());
+            m.insert("version".to_owned(), version.to_json());
+            m.insert("worker".to_owned(), claimed_by.unwrap_or_default().to_json());
+            m.insert("started".to_owned(), duration_to_str(krate.get(3)).to_json());
+            building.push(m.to_json());
+        } else {
+            crates.push((krate.get(0), krate.get(1)));
+        }
     }
     let is_empty = crates.is_empty();
-    Page::new(crates)
+
+    let mut content = BTreeMap::new();
+    content.insert("queue".to_owned(), crates.to_json());
+    content.insert("building".to_owned(), building.to_json());
+
+    Page::new(content)
         .title("Build queue")
         .set("description", "List of crates scheduled to build")
         .set_bool("queue_empty", is_empty)
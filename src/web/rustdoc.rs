@@ -2,6 +2,7 @@
 
 
 use super::pool::Pool;
+use super::storage::FileStorage;
 use super::file::File;
 use super::{latest_version, redirect_base};
 use super::crate_details::CrateDetails;
@@ -119,9 +120,9 @@ pub fn rustdoc_redirector_handler(req: &mut Request) -> IronResult<Response> {
         } else {
             let path = req.url.path();
             let path = path.join("/");
-            let conn = extension!(req, Pool);
-            match File::from_path(&conn, &path) {
-                Some(f) => return Ok(f.serve()),
+            let storage = extension!(req, FileStorage);
+            match File::from_path(&**storage, &path) {
+                Some(f) => return Ok(f.serve(req)),
                 None => return Err(IronError::new(Nope::ResourceNotFound, status::NotFound)),
             }
         }
@@ -200,6 +201,29 @@ pub fn rustdoc_html_server_handler(req: &mut Request) -> IronResult<Response> {
         MatchVersion::None => return Err(IronError::new(Nope::ResourceNotFound, status::NotFound)),
     };
 
+    // documentation of targets identical to the default target is only stored once, in the
+    // directory of the default target
+    if req_path.len() > 1 {
+        let rows = ctry!(conn.query("SELECT releases.identical_doc_targets
+                                     FROM releases
+                                     INNER JOIN crates ON crates.id = releases.crate_id
+                                     WHERE crates.name = $1 AND releases.version = $2",
+                                    &[&name, &version]));
+        let identical_targets: Option<Json> = if rows.is_empty() {
+            None
+        } else {
+            rows.get(0).get(0)
+        };
+        let is_identical = identical_targets.as_ref()
+            .and_then(|targets| targets.as_array())
+            .map_or(false, |targets| {
+                targets.iter().any(|target| target.as_string() == Some(req_path[0]))
+            });
+        if is_identical {
+            req_path.remove(0);
+        }
+    }
+
     // docs have "rustdoc" prefix in database
     req_path.insert(0, "rustdoc");
 
@@ -216,18 +240,20 @@ pub fn rustdoc_html_server_handler(req: &mut Request) -> IronResult<Response> {
         path
     };
 
-    let file = match File::from_path(&conn, &path) {
+    let storage = extension!(req, FileStorage);
+    let file = match File::from_path(&**storage, &path) {
         Some(f) => f,
         None => return Err(IronError::new(Nope::ResourceNotFound, status::NotFound)),
     };
 
     // serve file directly if it's not html
     if !path.ends_with(".html") {
-        return Ok(file.serve());
+        return Ok(file.serve(req));
     }
 
     let mut content = RustdocPage::default();
 
+    let file = ctry!(file.decompress());
     let file_content = ctry!(String::from_utf8(file.0.content));
 
     let (head, body, mut body_class) = ctry!(utils::extract_head_and_body(&file_content));
@@ -335,10 +361,10 @@ impl Handler for SharedResourceHandler {
         let filename = path.last().unwrap();  // unwrap is fine: vector is non-empty
         let suffix = filename.split('.').last().unwrap();  // unwrap is fine: split always works
         if ["js", "css", "woff", "svg"].contains(&suffix) {
-            let conn = extension!(req, Pool);
+            let storage = extension!(req, FileStorage);
 
-            if let Some(file) = File::from_path(conn, filename) {
-                return Ok(file.serve());
+            if let Some(file) = File::from_path(&**storage, filename) {
+                return Ok(file.serve(req));
             }
         }
 
@@ -346,3 +372,39 @@ impl Handler for SharedResourceHandler {
         Err(IronError::new(Nope::ResourceNotFound, status::NotFound))
     }
 }
+
+
+/// Serves a tarball of the documentation of a release, which can be browsed offline
+pub fn download_handler(req: &mut Request) -> IronResult<Response> {
+    use docbuilder::{offline_docs_file_name, offline_docs_path};
+
+    let router = extension!(req, Router);
+    let name = cexpect!(router.find("name"));
+    let conn = extension!(req, Pool);
+
+    let version = match match_version(&conn, &name, router.find("version")) {
+        MatchVersion::Exact(version) => version,
+        MatchVersion::Semver(version) => {
+            let url = ctry!(Url::parse(&format!("{}/crate/{}/{}/download",
+                                                redirect_base(req),
+                                                name,
+                                                version)[..]));
+            return Ok(super::redirect(url));
+        }
+        MatchVersion::None => return Err(IronError::new(Nope::CrateNotFound, status::NotFound)),
+    };
+
+    // only releases built after offline documentation was introduced have an archive
+    let storage = extension!(req, FileStorage);
+    match File::from_path(&**storage, &offline_docs_path(&name, &version)) {
+        Some(file) => {
+            let mut resp = file.serve(req);
+            resp.headers.set_raw("Content-Disposition",
+                                 vec![format!("attachment; filename=\"{}\"",
+                                              offline_docs_file_name(&name, &version))
+                                          .into_bytes()]);
+            Ok(resp)
+        }
+        None => Err(IronError::new(Nope::ResourceNotFound, status::NotFound)),
+    }
+}
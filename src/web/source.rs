@@ -6,6 +6,7 @@ use std::cmp::Ordering;
 use super::MetaData;
 use super::page::Page;
 use super::pool::Pool;
+use super::storage::FileStorage;
 use super::file::File as DbFile;
 use iron::prelude::*;
 use router::Router;
@@ -210,11 +211,12 @@ pub fn source_browser_handler(req: &mut Request) -> IronResult<Response> {
 
 
     let conn = extension!(req, Pool);
+    let storage = extension!(req, FileStorage);
 
     // try to get actual file first
     // skip if request is a directory
     let file = if !file_path.ends_with("/") {
-        DbFile::from_path(&conn, &file_path)
+        DbFile::from_path(&**storage, &file_path)
     } else {
         None
     };
@@ -223,9 +225,11 @@ pub fn source_browser_handler(req: &mut Request) -> IronResult<Response> {
     let (content, is_rust_source) = if let Some(file) = file {
         // serve the file with DatabaseFileHandler if file isn't text and not empty
         if !file.0.mime.starts_with("text") && !file.is_empty() {
-            return Ok(file.serve());
+            return Ok(file.serve(req));
         } else if file.0.mime.starts_with("text") && !file.is_empty() {
-            (String::from_utf8(file.0.content).ok(), file.0.path.ends_with(".rs"))
+            let is_rust_source = file.0.path.ends_with(".rs");
+            (file.decompress().ok().and_then(|file| String::from_utf8(file.0.content).ok()),
+             is_rust_source)
         } else {
             (None, false)
         }
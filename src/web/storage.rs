@@ -0,0 +1,27 @@
+use iron::prelude::*;
+use iron::{BeforeMiddleware, typemap};
+use std::sync::Arc;
+use storage::Storage;
+
+
+/// Middleware making the configured file storage available to handlers
+pub struct FileStorage {
+    storage: Arc<dyn Storage>,
+}
+
+impl typemap::Key for FileStorage {
+    type Value = Arc<dyn Storage>;
+}
+
+impl FileStorage {
+    pub fn new(storage: Arc<dyn Storage>) -> FileStorage {
+        FileStorage { storage: storage }
+    }
+}
+
+impl BeforeMiddleware for FileStorage {
+    fn before(&self, req: &mut Request) -> IronResult<()> {
+        req.extensions.insert::<FileStorage>(self.storage.clone());
+        Ok(())
+    }
+}